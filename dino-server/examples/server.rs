@@ -1,5 +1,5 @@
 use anyhow::Result;
-use dino_server::{start_server, ProjectConfig, SwappableAppRouter, TenentRouter};
+use dino_server::{start_server, ProjectConfig, ServerConfig, SwappableAppRouter, TenentRouter};
 use tracing::level_filters::LevelFilter;
 use tracing_subscriber::{fmt::Layer, layer::SubscriberExt, util::SubscriberInitExt, Layer as _};
 
@@ -28,9 +28,10 @@ async fn main() -> Result<()> {
 
     let routers = vec![TenentRouter::new(
         "localhost",
-        SwappableAppRouter::try_new(code, config.routes)?,
+        SwappableAppRouter::try_new(code, config)?,
     )];
 
-    start_server(8888, routers).await?;
+    let config = ServerConfig::builder().port(8888).build();
+    start_server(config, routers).await?;
     Ok(())
 }
@@ -0,0 +1,159 @@
+use crate::{config::deserialize_from_str, ProxyConfig, Res};
+use axum::http::{
+    header::{CONTENT_TYPE, LOCATION},
+    HeaderName, HeaderValue, StatusCode,
+};
+use indexmap::IndexMap;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// What a route does once it's matched. Only `Handler` calls into js.
+#[derive(Debug, Clone)]
+pub enum RouteAction {
+    Handler(String),
+    Redirect(RedirectConfig),
+    // internal path the request is dispatched to instead
+    Rewrite(String),
+    Respond(RespondConfig),
+    Proxy(ProxyConfig),
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct RedirectConfig {
+    // target path or url, `:name` / `*name` are replaced by path params
+    pub to: String,
+    #[serde(
+        default = "default_redirect_status",
+        deserialize_with = "deserialize_from_str"
+    )]
+    pub status: u16,
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct RespondConfig {
+    #[serde(
+        default = "default_respond_status",
+        deserialize_with = "deserialize_from_str"
+    )]
+    pub status: u16,
+    #[serde(default)]
+    pub headers: IndexMap<String, String>,
+    // strings are sent as is, anything else as json
+    #[serde(default)]
+    pub body: Option<serde_json::Value>,
+}
+
+fn default_redirect_status() -> u16 {
+    StatusCode::FOUND.as_u16()
+}
+
+fn default_respond_status() -> u16 {
+    StatusCode::OK.as_u16()
+}
+
+impl RedirectConfig {
+    pub fn res(&self, params: &HashMap<String, String>) -> Res {
+        let headers = HashMap::from([(LOCATION.to_string(), substitute(&self.to, params))]);
+        Res {
+            status: self.status,
+            headers,
+            body: None,
+        }
+    }
+}
+
+impl RespondConfig {
+    pub fn res(&self) -> Res {
+        let mut headers: HashMap<String, String> = self
+            .headers
+            .iter()
+            .map(|(k, v)| (k.to_lowercase(), v.clone()))
+            .collect();
+        let body = self.body.as_ref().map(|v| match v {
+            serde_json::Value::String(s) => s.clone(),
+            v => {
+                headers
+                    .entry(CONTENT_TYPE.to_string())
+                    .or_insert_with(|| mime::APPLICATION_JSON.to_string());
+                v.to_string()
+            }
+        });
+        Res {
+            status: self.status,
+            headers,
+            body,
+        }
+    }
+}
+
+/// Check that every header name and value can be sent.
+pub(crate) fn validate_headers(headers: &IndexMap<String, String>) -> Result<(), String> {
+    for (k, v) in headers {
+        HeaderName::from_bytes(k.as_bytes()).map_err(|_| format!("invalid header name: {k}"))?;
+        HeaderValue::from_str(v).map_err(|_| format!("invalid value of header {k}: {v}"))?;
+    }
+    Ok(())
+}
+
+/// Replace `:name` and `*name` segments of `target` with path params.
+pub fn substitute(target: &str, params: &HashMap<String, String>) -> String {
+    let mut ret = String::with_capacity(target.len());
+    let mut rest = target;
+    while let Some(idx) = rest.find([':', '*']) {
+        ret.push_str(&rest[..idx]);
+        let name_len = rest[idx + 1..]
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len() - idx - 1);
+        let name = &rest[idx + 1..idx + 1 + name_len];
+        match params.get(name) {
+            Some(v) => ret.push_str(v),
+            // not a param, e.g. the `:` of `https://`
+            None => ret.push_str(&rest[idx..idx + 1 + name_len]),
+        }
+        rest = &rest[idx + 1 + name_len..];
+    }
+    ret.push_str(rest);
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn substitute_should_work() {
+        let params = HashMap::from([
+            ("id".to_string(), "42".to_string()),
+            ("rest".to_string(), "a/b.css".to_string()),
+        ]);
+        assert_eq!(
+            substitute("/users/:id/profile", &params),
+            "/users/42/profile"
+        );
+        assert_eq!(
+            substitute("https://cdn.example.com:8443/*rest", &params),
+            "https://cdn.example.com:8443/a/b.css"
+        );
+        assert_eq!(substitute("/users/:name", &params), "/users/:name");
+    }
+
+    #[test]
+    fn respond_should_encode_body() {
+        let config: RespondConfig =
+            serde_yaml::from_str("body:\n  ok: true\nheaders:\n  X-Version: '1'").unwrap();
+        let res = config.res();
+        assert_eq!(res.status, 200);
+        assert_eq!(res.body.as_deref(), Some(r#"{"ok":true}"#));
+        assert_eq!(res.headers["content-type"], "application/json");
+        assert_eq!(res.headers["x-version"], "1");
+
+        let config: RedirectConfig = serde_yaml::from_str("to: /v2/:id\nstatus: 301").unwrap();
+        let params = HashMap::from([("id".to_string(), "7".to_string())]);
+        let res = config.res(&params);
+        assert_eq!(res.status, 301);
+        assert_eq!(res.headers["location"], "/v2/7");
+    }
+}
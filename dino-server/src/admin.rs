@@ -0,0 +1,593 @@
+use crate::{
+    host::tenant_key, metrics::get_metrics, middleware::auth_layer, AppError, AppRouterInner,
+    CanaryRule, CanaryStatus, JsWorker, ListenAddr, ProjectConfig, ShadowRule, ShadowStatus,
+    SwappableAppRouter, TenantRouters, VersionInfo,
+};
+use anyhow::anyhow;
+use axum::{
+    async_trait,
+    extract::{DefaultBodyLimit, FromRequestParts, Path, Query, Request, State},
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use dashmap::mapref::entry::Entry;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::task::spawn_blocking;
+use tokio_util::sync::CancellationToken;
+use tower_http::validate_request::ValidateRequestHeaderLayer;
+use tracing::info;
+
+// bundles are a lot bigger than regular requests
+const MAX_UPLOAD_SIZE: usize = 64 * 1024 * 1024;
+// top-level code of a bundle only sets up its handlers
+const BUILD_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Admin api to manage tenants at runtime, served on its own port.
+#[derive(Debug, Clone)]
+pub struct AdminConfig {
+    pub port: u16,
+    // it can deploy code, so it's only reachable locally, `127.0.0.1:{port}`,
+    // unless addresses are given
+    pub listen: Vec<ListenAddr>,
+    // every request needs `Authorization: Bearer <token>`
+    pub token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TenantInfo {
+    pub host: String,
+    pub hash: String,
+}
+
+/// A new build for a host.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Deployment {
+    // the bundled js
+    pub code: String,
+    // content of config.yml
+    pub config: String,
+    #[serde(default)]
+    pub profile: Option<String>,
+    // build hash reported by the cli, a hash of the code otherwise
+    #[serde(default)]
+    pub hash: Option<String>,
+}
+
+/// A build served next to the current one for the requests the rule picks.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CanaryDeployment {
+    #[serde(flatten)]
+    pub deployment: Deployment,
+    #[serde(default)]
+    pub rule: CanaryRule,
+}
+
+/// A build that gets a copy of sampled requests.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ShadowDeployment {
+    #[serde(flatten)]
+    pub deployment: Deployment,
+    #[serde(default)]
+    pub rule: ShadowRule,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Alias {
+    pub host: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Rollback {
+    // build hash to go back to, the previous version if not set
+    #[serde(default)]
+    pub to: Option<String>,
+}
+
+/// The tenant a request is about, `:host` of the path or, for keys with a base
+/// path like `example.com/billing`, `?key=` of the query.
+pub struct TenantKey(pub String);
+
+#[derive(Debug, Deserialize)]
+struct KeyQuery {
+    key: Option<String>,
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for TenantKey {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(query) = Query::<KeyQuery>::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        if let Some(key) = query.key {
+            return Ok(Self(key));
+        }
+        let Path(host) = Path::<String>::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        Ok(Self(host))
+    }
+}
+
+/// Routes of the admin api, sharing the tenants with the main server:
+/// - `GET /tenants`: list tenants with their build hash
+/// - `GET /tenants/:host`: show a single tenant
+/// - `PUT /tenants/:host`: deploy a build, swapping the router of an existing host
+/// - `DELETE /tenants/:host`: stop serving a host
+/// - `POST /tenants/:host/aliases`: serve another host with the same router
+/// - `GET /tenants/:host/versions`: list recent versions of a tenant
+/// - `POST /tenants/:host/rollback`: go back to a previous version
+/// - `GET /tenants/:host/canary`: show the canary and how much traffic it got
+/// - `PUT /tenants/:host/canary`: deploy a build as canary
+/// - `DELETE /tenants/:host/canary`: stop the canary
+/// - `POST /tenants/:host/canary/promote`: make the canary the current version
+/// - `GET /tenants/:host/shadow`: show the shadow and how its responses differ
+/// - `PUT /tenants/:host/shadow`: deploy a build as shadow
+/// - `DELETE /tenants/:host/shadow`: stop mirroring requests
+/// - `GET /metrics`: prometheus metrics of the server and every tenant
+///
+/// `:host` can't hold a `/`, tenants mounted under a base path are addressed
+/// as `/tenants/-?key=example.com/billing` instead, see [`TenantKey`].
+/// Certificates can't be attached here, tenants added at runtime get the
+/// startup certificate matching their host, or the default one.
+pub fn admin_router(routers: TenantRouters, token: &str) -> Router {
+    Router::new()
+        .route("/tenants", get(list_tenants))
+        .route(
+            "/tenants/:host",
+            get(get_tenant).put(deploy).delete(remove_tenant),
+        )
+        .route("/tenants/:host/aliases", post(add_alias))
+        .route("/tenants/:host/versions", get(list_versions))
+        .route("/tenants/:host/rollback", post(rollback))
+        .route(
+            "/tenants/:host/canary",
+            get(get_canary).put(deploy_canary).delete(remove_canary),
+        )
+        .route("/tenants/:host/canary/promote", post(promote_canary))
+        .route(
+            "/tenants/:host/shadow",
+            get(get_shadow).put(deploy_shadow).delete(remove_shadow),
+        )
+        .route("/metrics", get(get_metrics))
+        .layer(DefaultBodyLimit::max(MAX_UPLOAD_SIZE))
+        .layer(token_layer(token))
+        .with_state(routers)
+}
+
+async fn list_tenants(State(routers): State<TenantRouters>) -> Json<Vec<TenantInfo>> {
+    let tenants = routers
+        .entries()
+        .into_iter()
+        .map(|(host, router)| TenantInfo {
+            hash: router.load().hash.clone(),
+            host,
+        })
+        .collect();
+    Json(tenants)
+}
+
+async fn get_tenant(
+    State(routers): State<TenantRouters>,
+    TenantKey(host): TenantKey,
+) -> Result<Json<TenantInfo>, AppError> {
+    let host = tenant_key(&host);
+    let hash = routers
+        .get(&host)
+        .ok_or_else(|| AppError::HostNotFound(host.clone()))?
+        .load()
+        .hash
+        .clone();
+    Ok(Json(TenantInfo { host, hash }))
+}
+
+async fn deploy(
+    State(routers): State<TenantRouters>,
+    TenantKey(host): TenantKey,
+    Json(deployment): Json<Deployment>,
+) -> Result<(StatusCode, Json<TenantInfo>), AppError> {
+    let host = tenant_key(&host);
+    let inner = deployment.build().await?;
+    let hash = inner.hash.clone();
+
+    // existing hosts are swapped in place, so their aliases get the new build too
+    let status = routers.update(host.clone(), |entry| match entry {
+        Entry::Occupied(e) => {
+            e.get().store(inner, "admin");
+            StatusCode::OK
+        }
+        Entry::Vacant(e) => {
+            e.insert(inner.into());
+            StatusCode::CREATED
+        }
+    });
+    info!("deployed {} to {}", hash, host);
+    Ok((status, Json(TenantInfo { host, hash })))
+}
+
+async fn remove_tenant(
+    State(routers): State<TenantRouters>,
+    TenantKey(host): TenantKey,
+) -> Result<StatusCode, AppError> {
+    let host = tenant_key(&host);
+    routers
+        .remove(&host)
+        .ok_or_else(|| AppError::HostNotFound(host.clone()))?;
+    info!("removed {}", host);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn add_alias(
+    State(routers): State<TenantRouters>,
+    TenantKey(host): TenantKey,
+    Json(alias): Json<Alias>,
+) -> Result<(StatusCode, Json<TenantInfo>), AppError> {
+    let host = tenant_key(&host);
+    let router = routers.get(&host).ok_or(AppError::HostNotFound(host))?;
+    let alias = tenant_key(&alias.host);
+    routers.update(alias.clone(), |entry| match entry {
+        Entry::Occupied(_) => Err(AppError::HostExists(alias)),
+        Entry::Vacant(e) => {
+            let hash = router.load().hash.clone();
+            e.insert(router);
+            Ok((StatusCode::CREATED, Json(TenantInfo { host: alias, hash })))
+        }
+    })
+}
+
+async fn list_versions(
+    State(routers): State<TenantRouters>,
+    TenantKey(host): TenantKey,
+) -> Result<Json<Vec<VersionInfo>>, AppError> {
+    let router = tenant(&routers, host)?;
+    Ok(Json(router.versions()))
+}
+
+async fn rollback(
+    State(routers): State<TenantRouters>,
+    TenantKey(host): TenantKey,
+    body: Option<Json<Rollback>>,
+) -> Result<Json<VersionInfo>, AppError> {
+    let Json(rollback) = body.unwrap_or_default();
+    let router = tenant(&routers, host.clone())?;
+    let version = router.rollback(rollback.to.as_deref())?;
+    info!("rolled {} back to {}", host, version.hash);
+    Ok(Json(version))
+}
+
+async fn get_canary(
+    State(routers): State<TenantRouters>,
+    TenantKey(host): TenantKey,
+) -> Result<Json<CanaryStatus>, AppError> {
+    let router = tenant(&routers, host)?;
+    let canary = router
+        .canary()
+        .ok_or_else(|| AppError::VersionNotFound("canary".to_string()))?;
+    Ok(Json(canary))
+}
+
+async fn deploy_canary(
+    State(routers): State<TenantRouters>,
+    TenantKey(host): TenantKey,
+    Json(canary): Json<CanaryDeployment>,
+) -> Result<Json<CanaryStatus>, AppError> {
+    let router = tenant(&routers, host.clone())?;
+    let inner = canary.deployment.build().await?;
+    router.set_canary(inner, canary.rule)?;
+    let canary = router
+        .canary()
+        .ok_or_else(|| AppError::VersionNotFound("canary".to_string()))?;
+    info!("deployed canary {} to {}", canary.hash, host);
+    Ok(Json(canary))
+}
+
+async fn remove_canary(
+    State(routers): State<TenantRouters>,
+    TenantKey(host): TenantKey,
+) -> Result<StatusCode, AppError> {
+    let router = tenant(&routers, host)?;
+    router
+        .clear_canary()
+        .ok_or_else(|| AppError::VersionNotFound("canary".to_string()))?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn promote_canary(
+    State(routers): State<TenantRouters>,
+    TenantKey(host): TenantKey,
+) -> Result<Json<VersionInfo>, AppError> {
+    let router = tenant(&routers, host.clone())?;
+    let version = router.promote_canary()?;
+    info!("promoted canary {} on {}", version.hash, host);
+    Ok(Json(version))
+}
+
+async fn get_shadow(
+    State(routers): State<TenantRouters>,
+    TenantKey(host): TenantKey,
+) -> Result<Json<ShadowStatus>, AppError> {
+    let router = tenant(&routers, host)?;
+    let shadow = router
+        .shadow()
+        .ok_or_else(|| AppError::VersionNotFound("shadow".to_string()))?;
+    Ok(Json(shadow))
+}
+
+async fn deploy_shadow(
+    State(routers): State<TenantRouters>,
+    TenantKey(host): TenantKey,
+    Json(shadow): Json<ShadowDeployment>,
+) -> Result<Json<ShadowStatus>, AppError> {
+    let router = tenant(&routers, host.clone())?;
+    let inner = shadow.deployment.build().await?;
+    router.set_shadow(inner, shadow.rule)?;
+    let shadow = router
+        .shadow()
+        .ok_or_else(|| AppError::VersionNotFound("shadow".to_string()))?;
+    info!("deployed shadow {} to {}", shadow.hash, host);
+    Ok(Json(shadow))
+}
+
+async fn remove_shadow(
+    State(routers): State<TenantRouters>,
+    TenantKey(host): TenantKey,
+) -> Result<Json<ShadowStatus>, AppError> {
+    let router = tenant(&routers, host)?;
+    // the final numbers are returned, they are gone afterwards
+    let shadow = router
+        .clear_shadow()
+        .ok_or_else(|| AppError::VersionNotFound("shadow".to_string()))?;
+    Ok(Json(shadow))
+}
+
+fn tenant(routers: &TenantRouters, host: String) -> Result<SwappableAppRouter, AppError> {
+    let host = tenant_key(&host);
+    routers.get(&host).ok_or(AppError::HostNotFound(host))
+}
+
+impl Deployment {
+    async fn build(self) -> Result<AppRouterInner, AppError> {
+        let config = ProjectConfig::parse(&self.config, "config.yml", self.profile.as_deref())
+            .map_err(|e| AppError::InvalidConfig(e.to_string()))?;
+        // the config comes over the network, it can't read files of the server
+        if config.assets.is_some() {
+            let msg = "assets can't be deployed through the admin api".to_string();
+            return Err(AppError::InvalidConfig(msg));
+        }
+        if let Some(file) = config.schema_files().first() {
+            let msg = format!("schema {} must be inlined", file.display());
+            return Err(AppError::InvalidConfig(msg));
+        }
+        // a bundle that doesn't load would fail every request once swapped in.
+        // Its top-level code runs like a handler: on a blocking thread, with a timeout
+        let mut inner = spawn_blocking(move || {
+            let worker = JsWorker::try_new_with_interrupt(
+                &self.code,
+                Some(BUILD_TIMEOUT),
+                CancellationToken::new(),
+            )
+            .map_err(|e| AppError::InvalidCode(format!("{e:#}")))?;
+            if let Some(name) = config.handlers().find(|v| !worker.has_handler(v)) {
+                return Err(AppError::InvalidCode(format!("handler {name} not found")));
+            }
+            AppRouterInner::try_new(self.code, config)
+                .map_err(|e| AppError::InvalidConfig(e.to_string()))
+        })
+        .await
+        .map_err(|e| anyhow!(e))??;
+        if let Some(hash) = self.hash {
+            inner.hash = hash;
+        }
+        Ok(inner)
+    }
+}
+
+fn token_layer(
+    token: &str,
+) -> ValidateRequestHeaderLayer<impl FnMut(&mut Request) -> Result<(), Response> + Clone> {
+    // hashes compare in constant time
+    let expected = blake3::hash(token.as_bytes());
+    auth_layer("bearer", move |v| blake3::hash(v.as_bytes()) == expected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        body::Body,
+        http::{header::AUTHORIZATION, Method},
+    };
+    use serde::de::DeserializeOwned;
+    use tower::ServiceExt;
+
+    const CONFIG: &str = "name: test\nroutes:\n  /:\n    - method: GET\n      handler: hello\n";
+
+    async fn send(
+        app: &Router,
+        method: Method,
+        uri: &str,
+        body: Option<serde_json::Value>,
+    ) -> Response {
+        let req = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(AUTHORIZATION, "Bearer secret")
+            .header("content-type", "application/json")
+            .body(body.map_or(Body::empty(), |v| Body::from(v.to_string())))
+            .unwrap();
+        app.clone().oneshot(req).await.unwrap()
+    }
+
+    async fn json<T: DeserializeOwned>(res: Response) -> T {
+        let body = axum::body::to_bytes(res.into_body(), 1024).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    // a bundle whose `hello` handler answers with `version`
+    fn code(version: &str) -> String {
+        format!("(function(){{ async function hello(){{ return {{ status: 200, headers: {{}}, body: '{version}' }}; }} return {{ hello }}; }})();")
+    }
+
+    fn deployment(version: &str, hash: Option<&str>) -> Option<serde_json::Value> {
+        let code = code(version);
+        Some(serde_json::json!({ "code": code, "config": CONFIG, "hash": hash }))
+    }
+
+    #[tokio::test]
+    async fn admin_api_should_require_token() {
+        let app = admin_router(TenantRouters::default(), "secret");
+        let req = Request::builder()
+            .uri("/tenants")
+            .header(AUTHORIZATION, "Bearer wrong")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn admin_api_should_manage_tenants() {
+        let routers = TenantRouters::default();
+        let app = admin_router(routers.clone(), "secret");
+
+        let res = send(
+            &app,
+            Method::PUT,
+            "/tenants/a.com",
+            deployment("v1", Some("h1")),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let alias = Some(serde_json::json!({ "host": "b.com" }));
+        let res = send(&app, Method::POST, "/tenants/a.com/aliases", alias.clone()).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let res = send(&app, Method::POST, "/tenants/a.com/aliases", alias).await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+
+        // aliases share the router, so both hosts get the new build
+        let res = send(
+            &app,
+            Method::PUT,
+            "/tenants/a.com",
+            deployment("v2", Some("h2")),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(routers.get("b.com").unwrap().load().code, code("v2"));
+        let res = send(&app, Method::GET, "/tenants", None).await;
+        let tenants: Vec<TenantInfo> = json(res).await;
+        let tenants: Vec<_> = tenants.iter().map(|v| (&*v.host, &*v.hash)).collect();
+        assert_eq!(tenants, [("a.com", "h2"), ("b.com", "h2")]);
+
+        let bad = Some(serde_json::json!({ "code": "", "config": "name: 1\nroute: {}" }));
+        let res = send(&app, Method::PUT, "/tenants/c.com", bad).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let broken = Some(serde_json::json!({ "code": "(function(){", "config": CONFIG }));
+        let res = send(&app, Method::PUT, "/tenants/a.com", broken).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let missing = Some(serde_json::json!({ "code": "({ other() {} })", "config": CONFIG }));
+        let res = send(&app, Method::PUT, "/tenants/a.com", missing).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        let res = send(&app, Method::POST, "/tenants/a.com/rollback", None).await;
+        let version: VersionInfo = json(res).await;
+        assert_eq!(version.hash, "h1");
+        assert_eq!(routers.get("b.com").unwrap().load().code, code("v1"));
+        let res = send(&app, Method::GET, "/tenants/a.com/versions", None).await;
+        let versions: Vec<VersionInfo> = json(res).await;
+        let versions: Vec<_> = versions.iter().map(|v| (&*v.hash, v.current)).collect();
+        assert_eq!(versions, [("h2", false), ("h1", true)]);
+
+        let res = send(&app, Method::DELETE, "/tenants/a.com", None).await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        let res = send(&app, Method::GET, "/tenants/a.com", None).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        let res = send(&app, Method::GET, "/tenants/b.com", None).await;
+        let tenant: TenantInfo = json(res).await;
+        assert_eq!(tenant.hash, "h1");
+    }
+
+    #[tokio::test]
+    async fn admin_api_should_refuse_unsafe_deployments() {
+        let app = admin_router(TenantRouters::default(), "secret");
+        let endless = Some(serde_json::json!({ "code": "while (true) {}", "config": CONFIG }));
+        let res = send(&app, Method::PUT, "/tenants/a.com", endless).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        // files of the server can't be read
+        for (extra, error) in [
+            ("assets:\n  dir: /etc\n", "assets can't be deployed"),
+            (
+                "    - method: POST\n      handler: hello\n      request_schema: /etc/passwd\n",
+                "schema /etc/passwd must be inlined",
+            ),
+        ] {
+            let config = format!("{CONFIG}{extra}");
+            let body = Some(serde_json::json!({ "code": code("v1"), "config": config }));
+            let res = send(&app, Method::PUT, "/tenants/a.com", body).await;
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+            let body = axum::body::to_bytes(res.into_body(), 1024).await.unwrap();
+            assert!(String::from_utf8_lossy(&body).contains(error));
+        }
+    }
+
+    #[tokio::test]
+    async fn admin_api_should_take_mounted_keys_from_the_query() {
+        let routers = TenantRouters::default();
+        let app = admin_router(routers.clone(), "secret");
+
+        let uri = "/tenants/-?key=a.com/billing";
+        let res = send(&app, Method::PUT, uri, deployment("v1", Some("h1"))).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert!(routers.get("a.com/billing").is_some());
+        let res = send(
+            &app,
+            Method::GET,
+            "/tenants/-/versions?key=a.com/billing",
+            None,
+        )
+        .await;
+        let versions: Vec<VersionInfo> = json(res).await;
+        assert_eq!(versions[0].hash, "h1");
+        let res = send(&app, Method::GET, "/tenants/a.com%2Fbilling", None).await;
+        let tenant: TenantInfo = json(res).await;
+        assert_eq!(tenant.host, "a.com/billing");
+    }
+
+    #[tokio::test]
+    async fn admin_api_should_serve_metrics() {
+        let routers = TenantRouters::default();
+        let app = admin_router(routers.clone(), "secret");
+        for version in ["v1", "v2"] {
+            send(
+                &app,
+                Method::PUT,
+                "/tenants/m.com",
+                deployment(version, None),
+            )
+            .await;
+        }
+        let res = send(
+            &app,
+            Method::PUT,
+            "/tenants/m.com/canary",
+            deployment("v3", None),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = send(&app, Method::GET, "/metrics", None).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["content-type"], crate::METRICS_CONTENT_TYPE);
+        let body = axum::body::to_bytes(res.into_body(), 1 << 20)
+            .await
+            .unwrap();
+        let text = String::from_utf8(body.to_vec()).unwrap();
+        assert!(text.contains(r#"dino_router_swaps_total{source="admin"}"#));
+        assert!(text.contains(r#"dino_canary_requests_total{tenant="m.com",version="canary"} 0"#));
+    }
+}
@@ -0,0 +1,328 @@
+use crate::config::deserialize_from_str;
+use anyhow::Result;
+use axum::{
+    body::{Body, Bytes},
+    http::{
+        header::{
+            ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG, IF_NONE_MATCH, RANGE,
+        },
+        request::Parts,
+        Method, StatusCode,
+    },
+    response::Response,
+};
+use percent_encoding::percent_decode_str;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+const INDEX_FILE: &str = "index.html";
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct AssetsConfig {
+    pub dir: PathBuf,
+    // serve index.html for GET requests that match neither a route nor a file
+    #[serde(default, deserialize_with = "deserialize_from_str")]
+    pub spa_fallback: bool,
+    #[serde(default)]
+    pub priority: AssetsPriority,
+}
+
+/// Which side wins when a path matches both a route and a file.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum AssetsPriority {
+    #[default]
+    Routes,
+    Assets,
+}
+
+/// Static files of a project, loaded in memory when the router is (re)built.
+#[derive(Debug)]
+pub struct Assets {
+    files: HashMap<String, Asset>,
+    spa_fallback: bool,
+    priority: AssetsPriority,
+}
+
+#[derive(Debug)]
+struct Asset {
+    data: Bytes,
+    content_type: String,
+    etag: String,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum ByteRange {
+    Full,
+    Partial(usize, usize),
+    Unsatisfiable,
+}
+
+impl Assets {
+    pub fn load(config: &AssetsConfig) -> Result<Self> {
+        let mut files = HashMap::new();
+        load_dir(&config.dir, &config.dir, &mut files)?;
+        Ok(Self {
+            files,
+            spa_fallback: config.spa_fallback,
+            priority: config.priority,
+        })
+    }
+
+    /// Reuse the content of `other` for files that didn't change, so the
+    /// versions kept for rollback don't hold a copy of every file each.
+    pub fn share(&mut self, other: &Assets) {
+        let contents: HashMap<_, _> = other.files.values().map(|v| (&v.etag, &v.data)).collect();
+        for asset in self.files.values_mut() {
+            if let Some(data) = contents.get(&asset.etag) {
+                asset.data = Bytes::clone(data);
+            }
+        }
+    }
+
+    /// Serve a file before trying routes, if assets take priority.
+    pub fn serve_first(&self, parts: &Parts) -> Option<Response> {
+        if self.priority != AssetsPriority::Assets {
+            return None;
+        }
+        self.get(parts.uri.path()).map(|v| v.serve(parts))
+    }
+
+    /// Serve a file (or the SPA index) after no route matched.
+    pub fn serve_fallback(&self, parts: &Parts) -> Option<Response> {
+        let asset = match self.priority {
+            AssetsPriority::Routes => self.get(parts.uri.path()),
+            AssetsPriority::Assets => None,
+        };
+        asset
+            .or_else(|| {
+                self.spa_fallback
+                    .then(|| self.files.get(&format!("/{INDEX_FILE}")))
+                    .flatten()
+            })
+            .map(|v| v.serve(parts))
+    }
+
+    // files are keyed by their decoded path, dot segments never match one
+    fn get(&self, path: &str) -> Option<&Asset> {
+        let path = percent_decode_str(path).decode_utf8().ok()?;
+        if path.contains('\0') || path.split('/').any(|v| v == ".." || v == ".") {
+            return None;
+        }
+        if path.ends_with('/') {
+            return self.files.get(&format!("{path}{INDEX_FILE}"));
+        }
+        self.files.get(path.as_ref())
+    }
+}
+
+impl Asset {
+    fn serve(&self, parts: &Parts) -> Response {
+        let builder = Response::builder()
+            .header(CONTENT_TYPE, &self.content_type)
+            .header(ETAG, &self.etag)
+            .header(ACCEPT_RANGES, "bytes");
+
+        let not_modified = parts
+            .headers
+            .get(IF_NONE_MATCH)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| {
+                v.split(',')
+                    .any(|v| v.trim() == self.etag || v.trim() == "*")
+            });
+        if not_modified {
+            return builder
+                .status(StatusCode::NOT_MODIFIED)
+                .body(Body::empty())
+                .unwrap();
+        }
+
+        let len = self.data.len();
+        let range = parts.headers.get(RANGE).and_then(|v| v.to_str().ok());
+        let (builder, data) = match byte_range(range, len) {
+            ByteRange::Full => (builder.status(StatusCode::OK), self.data.clone()),
+            ByteRange::Partial(start, end) => (
+                builder
+                    .status(StatusCode::PARTIAL_CONTENT)
+                    .header(CONTENT_RANGE, format!("bytes {start}-{end}/{len}")),
+                self.data.slice(start..=end),
+            ),
+            ByteRange::Unsatisfiable => {
+                return builder
+                    .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                    .header(CONTENT_RANGE, format!("bytes */{len}"))
+                    .body(Body::empty())
+                    .unwrap();
+            }
+        };
+
+        let builder = builder.header(CONTENT_LENGTH, data.len());
+        if parts.method == Method::HEAD {
+            builder.body(Body::empty()).unwrap()
+        } else {
+            builder.body(data.into()).unwrap()
+        }
+    }
+}
+
+fn load_dir(root: &Path, dir: &Path, files: &mut HashMap<String, Asset>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            load_dir(root, &path, files)?;
+            continue;
+        }
+
+        let data = Bytes::from(fs::read(&path)?);
+        let key = path
+            .strip_prefix(root)?
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let asset = Asset {
+            content_type: mime_guess::from_path(&path)
+                .first_or_octet_stream()
+                .to_string(),
+            etag: format!("\"{}\"", &blake3::hash(&data).to_hex()[..16]),
+            data,
+        };
+        files.insert(format!("/{key}"), asset);
+    }
+    Ok(())
+}
+
+// only a single range is supported, anything else falls back to the full content
+fn byte_range(range: Option<&str>, len: usize) -> ByteRange {
+    let Some(spec) = range.and_then(|v| v.strip_prefix("bytes=")) else {
+        return ByteRange::Full;
+    };
+    if spec.contains(',') {
+        return ByteRange::Full;
+    }
+    let Some((start, end)) = spec.trim().split_once('-') else {
+        return ByteRange::Full;
+    };
+
+    let last = len.saturating_sub(1);
+    let (start, end) = match (start.parse::<usize>(), end.parse::<usize>()) {
+        (Ok(start), Ok(end)) => (start, end.min(last)),
+        (Ok(start), Err(_)) if end.is_empty() => (start, last),
+        (Err(_), Ok(suffix)) if start.is_empty() => (len.saturating_sub(suffix), last),
+        _ => return ByteRange::Full,
+    };
+
+    if start > end || start >= len {
+        ByteRange::Unsatisfiable
+    } else {
+        ByteRange::Partial(start, end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Request;
+
+    fn parts(method: Method, path: &str, headers: &[(&str, &str)]) -> Parts {
+        let mut builder = Request::builder().method(method).uri(path);
+        for (k, v) in headers {
+            builder = builder.header(*k, *v);
+        }
+        builder.body(()).unwrap().into_parts().0
+    }
+
+    fn assets(priority: AssetsPriority) -> Assets {
+        let config = AssetsConfig {
+            dir: PathBuf::from("fixtures/public"),
+            spa_fallback: true,
+            priority,
+        };
+        Assets::load(&config).unwrap()
+    }
+
+    #[test]
+    fn byte_range_should_work() {
+        assert_eq!(byte_range(None, 10), ByteRange::Full);
+        assert_eq!(byte_range(Some("bytes=0-4"), 10), ByteRange::Partial(0, 4));
+        assert_eq!(byte_range(Some("bytes=5-"), 10), ByteRange::Partial(5, 9));
+        assert_eq!(byte_range(Some("bytes=-3"), 10), ByteRange::Partial(7, 9));
+        assert_eq!(
+            byte_range(Some("bytes=0-100"), 10),
+            ByteRange::Partial(0, 9)
+        );
+        assert_eq!(byte_range(Some("bytes=10-"), 10), ByteRange::Unsatisfiable);
+        assert_eq!(byte_range(Some("bytes=0-1,3-4"), 10), ByteRange::Full);
+    }
+
+    #[test]
+    fn assets_should_serve_files() {
+        let assets = assets(AssetsPriority::Routes);
+        assert!(assets.serve_first(&parts(Method::GET, "/", &[])).is_none());
+
+        let res = assets
+            .serve_fallback(&parts(Method::GET, "/css/app.css", &[]))
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[CONTENT_TYPE], "text/css");
+        let etag = res.headers()[ETAG].to_str().unwrap().to_string();
+
+        let req = parts(Method::GET, "/css/app.css", &[("if-none-match", &etag)]);
+        let res = assets.serve_fallback(&req).unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+
+        let req = parts(Method::GET, "/css/app.css", &[("range", "bytes=0-3")]);
+        let res = assets.serve_fallback(&req).unwrap();
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(res.headers()[CONTENT_LENGTH], "4");
+    }
+
+    #[test]
+    fn assets_should_share_unchanged_files() {
+        let old = assets(AssetsPriority::Routes);
+        let mut new = assets(AssetsPriority::Routes);
+        let path = "/css/app.css";
+        assert_ne!(old.files[path].data.as_ptr(), new.files[path].data.as_ptr());
+        new.share(&old);
+        assert_eq!(old.files[path].data.as_ptr(), new.files[path].data.as_ptr());
+    }
+
+    #[test]
+    fn assets_should_decode_paths() {
+        let assets = assets(AssetsPriority::Assets);
+        let res = assets
+            .serve_first(&parts(Method::GET, "/css/%61pp.css", &[]))
+            .unwrap();
+        assert_eq!(res.headers()[CONTENT_TYPE], "text/css");
+
+        for path in [
+            "/css/%2e%2e/css/app.css",
+            "/css/./app.css",
+            "/css/app.css%00",
+        ] {
+            assert!(assets.serve_first(&parts(Method::GET, path, &[])).is_none());
+        }
+    }
+
+    #[test]
+    fn assets_should_fallback_to_index() {
+        let assets = assets(AssetsPriority::Assets);
+        let res = assets.serve_first(&parts(Method::GET, "/", &[])).unwrap();
+        assert_eq!(res.headers()[CONTENT_TYPE], "text/html");
+
+        assert!(assets
+            .serve_first(&parts(Method::GET, "/app/settings", &[]))
+            .is_none());
+        let res = assets
+            .serve_fallback(&parts(Method::GET, "/app/settings", &[]))
+            .unwrap();
+        assert_eq!(res.headers()[CONTENT_TYPE], "text/html");
+    }
+}
@@ -0,0 +1,344 @@
+use crate::{
+    config::{deserialize_size, size_schema},
+    AppError,
+};
+use anyhow::{anyhow, Result};
+use axum::body::{to_bytes, Body, Bytes, HttpBody};
+use dino_macros::IntoJs;
+use http_body_util::LengthLimitError;
+use mime::Mime;
+use rquickjs::{ArrayBuffer, Ctx, Exception, Value};
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+const KB: usize = 1024;
+const MB: usize = 1024 * KB;
+
+/// Maximum body size accepted for each kind of request body.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(default)]
+#[schemars(deny_unknown_fields)]
+pub struct BodyLimits {
+    #[serde(deserialize_with = "deserialize_size")]
+    #[schemars(schema_with = "size_schema")]
+    pub json: usize,
+    #[serde(deserialize_with = "deserialize_size")]
+    #[schemars(schema_with = "size_schema")]
+    pub form: usize,
+    #[serde(deserialize_with = "deserialize_size")]
+    #[schemars(schema_with = "size_schema")]
+    pub multipart: usize,
+    #[serde(deserialize_with = "deserialize_size")]
+    #[schemars(schema_with = "size_schema")]
+    pub raw: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyKind {
+    Json,
+    Form,
+    Multipart,
+    Raw,
+}
+
+/// Raw body bytes, exposed to js as an `ArrayBuffer`.
+#[derive(Debug, Clone)]
+pub struct RawBody(pub Bytes);
+
+/// A single field of a urlencoded or multipart form. File parts carry
+/// `filename`, `content_type` and `data`, plain fields only carry `value`.
+#[derive(Debug, Default, IntoJs)]
+pub struct FormEntry {
+    pub name: String,
+    pub value: Option<String>,
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+    pub data: Option<RawBody>,
+}
+
+impl Default for BodyLimits {
+    fn default() -> Self {
+        Self {
+            json: MB,
+            form: MB,
+            multipart: 10 * MB,
+            raw: MB,
+        }
+    }
+}
+
+impl BodyLimits {
+    pub fn limit(&self, kind: BodyKind) -> usize {
+        match kind {
+            BodyKind::Json => self.json,
+            BodyKind::Form => self.form,
+            BodyKind::Multipart => self.multipart,
+            BodyKind::Raw => self.raw,
+        }
+    }
+
+    pub fn limit_for(&self, content_type: Option<&str>) -> usize {
+        self.limit(BodyKind::from_content_type(content_type))
+    }
+}
+
+/// Read the whole body, failing with 413 as soon as it exceeds the limit.
+pub async fn read_body(body: Body, limit: usize) -> Result<Bytes, AppError> {
+    if body.size_hint().lower() > limit as u64 {
+        return Err(AppError::PayloadTooLarge(limit));
+    }
+
+    match to_bytes(body, limit).await {
+        Ok(bytes) => Ok(bytes),
+        Err(e) => {
+            let e = e.into_inner();
+            if e.is::<LengthLimitError>() {
+                Err(AppError::PayloadTooLarge(limit))
+            } else {
+                Err(anyhow!(e).into())
+            }
+        }
+    }
+}
+
+impl BodyKind {
+    pub fn from_content_type(content_type: Option<&str>) -> Self {
+        let Some(mime) = content_type.and_then(|v| v.parse::<Mime>().ok()) else {
+            return BodyKind::Raw;
+        };
+
+        match (mime.type_(), mime.subtype(), mime.suffix()) {
+            (mime::APPLICATION, mime::JSON, _) | (_, _, Some(mime::JSON)) => BodyKind::Json,
+            (mime::APPLICATION, mime::WWW_FORM_URLENCODED, _) => BodyKind::Form,
+            (mime::MULTIPART, mime::FORM_DATA, _) => BodyKind::Multipart,
+            _ => BodyKind::Raw,
+        }
+    }
+}
+
+impl<'js> rquickjs::IntoJs<'js> for RawBody {
+    fn into_js(self, ctx: &Ctx<'js>) -> rquickjs::Result<Value<'js>> {
+        Ok(ArrayBuffer::new(ctx.clone(), self.0.to_vec())?.into_value())
+    }
+}
+
+/// Parse a urlencoded or multipart body into form entries based on its content type.
+pub fn parse_form(content_type: &str, body: &[u8]) -> Result<Vec<FormEntry>> {
+    let mime: Mime = content_type.parse()?;
+    match BodyKind::from_content_type(Some(content_type)) {
+        BodyKind::Form => {
+            let pairs: Vec<(String, String)> = serde_urlencoded::from_bytes(body)?;
+            let entries = pairs
+                .into_iter()
+                .map(|(name, value)| FormEntry {
+                    name,
+                    value: Some(value),
+                    ..Default::default()
+                })
+                .collect();
+            Ok(entries)
+        }
+        BodyKind::Multipart => {
+            let boundary = mime
+                .get_param(mime::BOUNDARY)
+                .ok_or_else(|| anyhow!("missing multipart boundary"))?;
+            parse_multipart(boundary.as_str(), body)
+        }
+        _ => Err(anyhow!(
+            "unsupported content type for form data: {content_type}"
+        )),
+    }
+}
+
+// js entry of `req.formData()`
+pub(crate) fn js_parse_form<'js>(
+    ctx: Ctx<'js>,
+    content_type: String,
+    body: Option<ArrayBuffer<'js>>,
+) -> rquickjs::Result<Vec<FormEntry>> {
+    let body = body.as_ref().and_then(|v| v.as_bytes()).unwrap_or_default();
+    parse_form(&content_type, body).map_err(|e| Exception::throw_type(&ctx, &e.to_string()))
+}
+
+// js entry of `file.text()`
+pub(crate) fn js_decode_utf8(body: Option<ArrayBuffer<'_>>) -> String {
+    let body = body.as_ref().and_then(|v| v.as_bytes()).unwrap_or_default();
+    String::from_utf8_lossy(body).into_owned()
+}
+
+fn parse_multipart(boundary: &str, body: &[u8]) -> Result<Vec<FormEntry>> {
+    // a delimiter starts a line, so `--boundary` inside a part doesn't end it
+    let delimiter = format!("\r\n--{boundary}");
+    let delimiter = delimiter.as_bytes();
+
+    // only the first delimiter may start the body instead of a line
+    let mut pos = match body.starts_with(&delimiter[2..]) {
+        true => delimiter.len() - 2,
+        false => {
+            find(body, delimiter, 0).ok_or_else(|| anyhow!("missing multipart boundary"))?
+                + delimiter.len()
+        }
+    };
+    let mut entries = Vec::new();
+    loop {
+        // closing delimiter
+        if body[pos..].starts_with(b"--") {
+            break;
+        }
+        let start = pos
+            + if body[pos..].starts_with(b"\r\n") {
+                2
+            } else {
+                0
+            };
+        let end =
+            find(body, delimiter, start).ok_or_else(|| anyhow!("unterminated multipart body"))?;
+        entries.push(parse_part(&body[start..end])?);
+        pos = end + delimiter.len();
+    }
+
+    Ok(entries)
+}
+
+fn parse_part(part: &[u8]) -> Result<FormEntry> {
+    let sep = find(part, b"\r\n\r\n", 0).ok_or_else(|| anyhow!("invalid multipart part"))?;
+    let headers = std::str::from_utf8(&part[..sep])?;
+    let data = &part[sep + 4..];
+
+    let mut entry = FormEntry::default();
+    for line in headers.split("\r\n") {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        match key.trim().to_lowercase().as_str() {
+            "content-disposition" => {
+                for param in value.split(';').skip(1) {
+                    let Some((k, v)) = param.split_once('=') else {
+                        continue;
+                    };
+                    let v = v.trim().trim_matches('"').to_string();
+                    match k.trim() {
+                        "name" => entry.name = v,
+                        "filename" => entry.filename = Some(v),
+                        _ => {}
+                    }
+                }
+            }
+            "content-type" => entry.content_type = Some(value.trim().to_string()),
+            _ => {}
+        }
+    }
+
+    if entry.filename.is_some() {
+        entry.data = Some(RawBody(Bytes::copy_from_slice(data)));
+    } else {
+        entry.value = Some(String::from_utf8_lossy(data).into_owned());
+    }
+    Ok(entry)
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|w| w == needle)
+        .map(|i| i + from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn body_kind_should_dispatch_on_content_type() {
+        assert_eq!(
+            BodyKind::from_content_type(Some("application/json; charset=utf-8")),
+            BodyKind::Json
+        );
+        assert_eq!(
+            BodyKind::from_content_type(Some("application/vnd.api+json")),
+            BodyKind::Json
+        );
+        assert_eq!(
+            BodyKind::from_content_type(Some("application/x-www-form-urlencoded")),
+            BodyKind::Form
+        );
+        assert_eq!(
+            BodyKind::from_content_type(Some("multipart/form-data; boundary=abc")),
+            BodyKind::Multipart
+        );
+        assert_eq!(
+            BodyKind::from_content_type(Some("text/plain")),
+            BodyKind::Raw
+        );
+        assert_eq!(BodyKind::from_content_type(None), BodyKind::Raw);
+    }
+
+    #[test]
+    fn parse_urlencoded_form_should_work() {
+        let entries = parse_form(
+            "application/x-www-form-urlencoded",
+            b"name=tyr&tag=a&tag=b%20c",
+        )
+        .unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].name, "name");
+        assert_eq!(entries[0].value.as_deref(), Some("tyr"));
+        assert_eq!(entries[2].value.as_deref(), Some("b c"));
+    }
+
+    #[test]
+    fn parse_multipart_form_should_work() {
+        let body = "--XyZ\r\n\
+            Content-Disposition: form-data; name=\"title\"\r\n\
+            \r\n\
+            hello\r\n\
+            --XyZ\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\
+            Content-Type: text/plain\r\n\
+            \r\n\
+            line1\r\nline2\r\n\
+            --XyZ--\r\n";
+        let entries = parse_form("multipart/form-data; boundary=XyZ", body.as_bytes()).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].name, "title");
+        assert_eq!(entries[0].value.as_deref(), Some("hello"));
+        assert_eq!(entries[1].name, "file");
+        assert_eq!(entries[1].filename.as_deref(), Some("a.txt"));
+        assert_eq!(entries[1].content_type.as_deref(), Some("text/plain"));
+        assert_eq!(
+            entries[1].data.as_ref().unwrap().0,
+            Bytes::from_static(b"line1\r\nline2")
+        );
+    }
+
+    #[test]
+    fn parse_multipart_form_should_only_split_on_line_delimiters() {
+        let body = "preamble\r\n--XyZ\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\
+            \r\n\
+            a --XyZ b\r\n\
+            --XyZ--\r\n";
+        let entries = parse_form("multipart/form-data; boundary=XyZ", body.as_bytes()).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(
+            entries[0].data.as_ref().unwrap().0,
+            Bytes::from_static(b"a --XyZ b")
+        );
+    }
+
+    #[tokio::test]
+    async fn body_limits_should_reject_large_body() {
+        let limits = BodyLimits {
+            json: 4,
+            ..Default::default()
+        };
+        let limit = limits.limit_for(Some("application/json"));
+        let ret = read_body(Body::from("[1,2,3]"), limit).await;
+        assert!(matches!(ret, Err(AppError::PayloadTooLarge(4))));
+
+        let limit = limits.limit_for(Some("text/plain"));
+        let ret = read_body(Body::from("[1,2,3]"), limit).await;
+        assert_eq!(ret.unwrap(), Bytes::from_static(b"[1,2,3]"));
+    }
+}
@@ -0,0 +1,269 @@
+use crate::{AppRouter, AppRouterInner};
+use axum::{
+    http::{
+        header::{COOKIE, SET_COOKIE},
+        HeaderMap, HeaderValue,
+    },
+    response::Response,
+};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+/// Response header telling which version served the request.
+pub const VERSION_HEADER: &str = "x-dino-version";
+
+/// Decides which requests the canary serves. A matching header always wins,
+/// then the sticky cookie, then the percentage.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CanaryRule {
+    // share of the remaining traffic sent to the canary, 0 - 100
+    #[serde(default)]
+    pub percent: u8,
+    // e.g. `x-canary: true` to try the canary explicitly
+    #[serde(default)]
+    pub header: Option<HeaderRule>,
+    // cookie remembering the version a client got, so it keeps getting it
+    #[serde(default)]
+    pub cookie: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HeaderRule {
+    pub name: String,
+    pub value: String,
+}
+
+/// The version picked for a request.
+pub struct Selection {
+    pub router: AppRouter,
+    pub canary: bool,
+    // pins the client to the picked version
+    pub cookie: Option<String>,
+}
+
+/// A second version served next to the current one.
+pub(crate) struct Canary {
+    pub(crate) router: Arc<AppRouterInner>,
+    pub(crate) rule: CanaryRule,
+    pub(crate) stable_requests: AtomicU64,
+    pub(crate) canary_requests: AtomicU64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CanaryStatus {
+    // build hash of the canary
+    pub hash: String,
+    // build hash of the version serving the rest
+    pub stable: String,
+    pub rule: CanaryRule,
+    pub stable_requests: u64,
+    pub canary_requests: u64,
+}
+
+impl CanaryRule {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.percent > 100 {
+            return Err(format!(
+                "canary percent must be 0 - 100, got {}",
+                self.percent
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Canary {
+    pub(crate) fn new(router: Arc<AppRouterInner>, rule: CanaryRule) -> Self {
+        Self {
+            router,
+            rule,
+            stable_requests: AtomicU64::new(0),
+            canary_requests: AtomicU64::new(0),
+        }
+    }
+
+    /// Whether the canary serves the request, and the cookie to set if the
+    /// client isn't pinned to a version yet.
+    pub(crate) fn choose(&self, headers: &HeaderMap, stable: &str) -> (bool, Option<String>) {
+        let rule = &self.rule;
+        if let Some(h) = &rule.header {
+            let matched = headers
+                .get_all(h.name.as_str())
+                .iter()
+                .any(|v| v.as_bytes() == h.value.as_bytes());
+            if matched {
+                return self.record(true, None);
+            }
+        }
+
+        let Some(name) = &rule.cookie else {
+            return self.record(self.roll(), None);
+        };
+        match cookie(headers, name) {
+            Some(v) if v == self.router.hash => self.record(true, None),
+            Some(v) if v == stable => self.record(false, None),
+            // no cookie yet, or one for a version that is gone
+            _ => {
+                let canary = self.roll();
+                let hash = if canary { &self.router.hash } else { stable };
+                self.record(
+                    canary,
+                    Some(format!("{name}={hash}; Path=/; HttpOnly; SameSite=Lax")),
+                )
+            }
+        }
+    }
+
+    pub(crate) fn status(&self, stable: &str) -> CanaryStatus {
+        CanaryStatus {
+            hash: self.router.hash.clone(),
+            stable: stable.to_string(),
+            rule: self.rule.clone(),
+            stable_requests: self.stable_requests.load(Ordering::Relaxed),
+            canary_requests: self.canary_requests.load(Ordering::Relaxed),
+        }
+    }
+
+    fn roll(&self) -> bool {
+        rand::thread_rng().gen_range(0..100) < self.rule.percent
+    }
+
+    fn record(&self, canary: bool, cookie: Option<String>) -> (bool, Option<String>) {
+        let counter = if canary {
+            &self.canary_requests
+        } else {
+            &self.stable_requests
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        (canary, cookie)
+    }
+}
+
+impl Selection {
+    /// Pin the client to the version that served it, the cookie is only sent
+    /// back over https when it was set over https. The version is told as
+    /// `stable` or `canary`, or as the build hash when `version_hash` is set.
+    pub fn apply(&self, res: &mut Response, secure: bool, version_hash: bool) {
+        let headers = res.headers_mut();
+        let version = match (version_hash, self.canary) {
+            (true, _) => HeaderValue::from_str(&self.router.hash).ok(),
+            (false, true) => Some(HeaderValue::from_static("canary")),
+            (false, false) => Some(HeaderValue::from_static("stable")),
+        };
+        if let Some(v) = version {
+            headers.insert(VERSION_HEADER, v);
+        }
+        let cookie = match (&self.cookie, secure) {
+            (Some(v), true) => Some(format!("{v}; Secure")),
+            (v, _) => v.clone(),
+        };
+        if let Some(v) = cookie.and_then(|v| HeaderValue::from_str(&v).ok()) {
+            headers.append(SET_COOKIE, v);
+        }
+    }
+}
+
+fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|v| v.trim().split_once('='))
+        .find(|(k, _)| *k == name)
+        .map(|(_, v)| v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ProjectConfig;
+
+    fn canary(rule: CanaryRule) -> Canary {
+        let config: ProjectConfig = serde_yaml::from_str("name: test\nroutes: {}").unwrap();
+        let mut inner = AppRouterInner::try_new("", config).unwrap();
+        inner.hash = "new".to_string();
+        Canary::new(Arc::new(inner), rule)
+    }
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.parse().unwrap(), v.parse().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn canary_should_follow_header_then_cookie_then_percent() {
+        let rule = CanaryRule {
+            percent: 0,
+            header: Some(HeaderRule {
+                name: "x-canary".to_string(),
+                value: "true".to_string(),
+            }),
+            cookie: Some("version".to_string()),
+        };
+        let canary = canary(rule);
+
+        let (is_canary, cookie) = canary.choose(&headers(&[("x-canary", "true")]), "old");
+        assert!(is_canary && cookie.is_none());
+        let (is_canary, cookie) = canary.choose(&headers(&[("cookie", "a=1; version=new")]), "old");
+        assert!(is_canary && cookie.is_none());
+        // unknown versions are rolled again and pinned
+        let (is_canary, cookie) = canary.choose(&headers(&[("cookie", "version=gone")]), "old");
+        assert!(!is_canary);
+        assert_eq!(
+            cookie.as_deref(),
+            Some("version=old; Path=/; HttpOnly; SameSite=Lax")
+        );
+
+        let status = canary.status("old");
+        assert_eq!((status.canary_requests, status.stable_requests), (2, 1));
+    }
+
+    #[test]
+    fn canary_should_split_by_percent() {
+        let all = canary(CanaryRule {
+            percent: 100,
+            ..Default::default()
+        });
+        assert!((0..100).all(|_| all.choose(&HeaderMap::new(), "old").0));
+        let none = canary(CanaryRule::default());
+        assert!((0..100).all(|_| !none.choose(&HeaderMap::new(), "old").0));
+        assert!(CanaryRule {
+            percent: 101,
+            ..Default::default()
+        }
+        .validate()
+        .is_err());
+    }
+
+    #[test]
+    fn selection_should_pin_clients_with_a_lax_cookie() {
+        let config: crate::ProjectConfig = serde_yaml::from_str("name: t\nroutes: {}").unwrap();
+        let mut inner = AppRouterInner::try_new("", config).unwrap();
+        inner.hash = "h1".to_string();
+        let selection = Selection {
+            router: crate::SwappableAppRouter::from(inner).load(),
+            canary: false,
+            cookie: Some("version=h1; Path=/; HttpOnly; SameSite=Lax".to_string()),
+        };
+        let mut res = Response::new(Default::default());
+        selection.apply(&mut res, false, false);
+        assert_eq!(res.headers()[VERSION_HEADER], "stable");
+        assert!(!res.headers()[SET_COOKIE]
+            .to_str()
+            .unwrap()
+            .contains("Secure"));
+
+        let mut res = Response::new(Default::default());
+        selection.apply(&mut res, true, true);
+        assert_eq!(res.headers()[VERSION_HEADER], "h1");
+        let cookie = res.headers()[SET_COOKIE].to_str().unwrap();
+        assert!(cookie.ends_with("SameSite=Lax; Secure"));
+    }
+}
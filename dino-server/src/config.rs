@@ -1,45 +1,760 @@
-use crate::ProjectRoutes;
+use crate::actions::validate_headers;
+use crate::proxy::{deserialize_proxy, proxy_schema};
+use crate::{
+    apply_profile, env_var, interpolate, load_schema_file, validate::locate_path, validate_config,
+    AdminConfig, AssetsConfig, BodyLimits, ConfigError, ConfigIssue, ListenAddr, OpenApiConfig,
+    ProjectRoutes, ProxyConfig, RedirectConfig, RequestLimits, RespondConfig, RouteAction,
+    ShutdownHandle, TlsConfig,
+};
 use anyhow::Result;
-use axum::http::Method;
+use axum::http::{HeaderValue, Method, StatusCode};
+use indexmap::IndexMap;
+use schemars::{
+    gen::SchemaGenerator,
+    schema::{InstanceType, Schema, SchemaObject, SubschemaValidation},
+    schema_for, JsonSchema,
+};
 use serde::{Deserialize, Deserializer};
-use std::path::Path;
+use serde_json::json;
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+use typed_builder::TypedBuilder;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct ProjectConfig {
     pub name: String,
+    #[serde(default)]
+    pub body: BodyLimits,
+    // binding name => host of another tenant, exposed to js as `env.NAME`
+    #[serde(default)]
+    pub services: IndexMap<String, String>,
+    #[serde(default)]
+    pub assets: Option<AssetsConfig>,
+    #[serde(default)]
+    pub limits: RequestLimits,
+    // serve an OpenAPI document describing the routes
+    #[serde(default)]
+    pub openapi: Option<OpenApiConfig>,
+    // how request paths are normalized before routes are matched
+    #[serde(default)]
+    pub matching: PathMatching,
     pub routes: ProjectRoutes,
 }
 
-#[derive(Debug, Deserialize)]
+pub(crate) const ACTION_KEYS: [&str; 5] = ["handler", "redirect", "rewrite", "respond", "proxy"];
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct ProjectRoute {
-    #[serde(deserialize_with = "deserialize_method")]
-    pub method: Method,
-    pub handler: String,
+    // a single method, a list of methods, or ANY
+    #[serde(rename = "method", deserialize_with = "deserialize_methods")]
+    #[schemars(schema_with = "methods_schema")]
+    pub methods: Vec<RouteMethod>,
+    // exactly one of handler, redirect, rewrite or respond
+    #[serde(default)]
+    pub handler: Option<String>,
+    #[serde(default)]
+    pub redirect: Option<RedirectConfig>,
+    #[serde(default)]
+    pub rewrite: Option<String>,
+    #[serde(default)]
+    pub respond: Option<RespondConfig>,
+    #[serde(default, deserialize_with = "deserialize_proxy")]
+    #[schemars(schema_with = "proxy_schema")]
+    pub proxy: Option<ProxyConfig>,
+    #[serde(default)]
+    pub limits: RequestLimits,
+    // only used to document the route in the OpenAPI document
+    #[serde(default)]
+    pub summary: Option<String>,
+    // JSON Schemas checked before js runs, inline or the path of a json / yaml file
+    #[serde(default)]
+    pub request_schema: Option<serde_json::Value>,
+    #[serde(default)]
+    pub query_schema: Option<serde_json::Value>,
+    #[serde(default)]
+    pub params_schema: Option<serde_json::Value>,
+    // JSON Schema of the response body
+    #[serde(default)]
+    pub response_schema: Option<serde_json::Value>,
+    #[serde(flatten)]
+    pub options: RouteOptions,
+}
+
+/// Per route policies, each enforced by a tower layer around the handler.
+#[derive(Debug, Default, Clone, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct RouteOptions {
+    #[serde(default, deserialize_with = "deserialize_opt_duration")]
+    #[schemars(schema_with = "duration_schema")]
+    pub timeout: Option<Duration>,
+    #[serde(default)]
+    pub cors: Option<CorsConfig>,
+    // required scheme of the `Authorization` header, e.g. "bearer". Only
+    // checks that a credential is present, the handler must validate it
+    #[serde(default)]
+    pub auth: Option<String>,
+    // max-age of the `Cache-Control` header set on successful GET / HEAD
+    // responses, `private` for routes with `auth`
+    #[serde(default, deserialize_with = "deserialize_opt_duration")]
+    #[schemars(schema_with = "duration_schema")]
+    pub cache: Option<Duration>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct CorsConfig {
+    // "*" allows any origin
+    #[serde(default)]
+    pub origins: Vec<String>,
+    // empty or "*" means whatever the preflight request asks for
+    #[serde(default)]
+    pub methods: Vec<String>,
+    #[serde(default)]
+    pub headers: Vec<String>,
+    #[serde(default, deserialize_with = "deserialize_from_str")]
+    pub credentials: bool,
+    #[serde(default, deserialize_with = "deserialize_opt_duration")]
+    #[schemars(schema_with = "duration_schema")]
+    pub max_age: Option<Duration>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize, JsonSchema)]
+#[serde(default)]
+#[schemars(deny_unknown_fields)]
+pub struct PathMatching {
+    pub trailing_slash: TrailingSlash,
+    // routes match whatever the case of the path, params keep their case
+    #[serde(deserialize_with = "deserialize_from_str")]
+    pub case_insensitive: bool,
+}
+
+/// What to do when a path only matches with a trailing slash added or removed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum TrailingSlash {
+    // `/users/` and `/users` are different paths
+    #[default]
+    Strict,
+    // permanently redirect to the path that matches
+    Redirect,
+    // serve the path that matches
+    Ignore,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RouteMethod {
+    Any,
+    Method(Method),
+}
+
+#[derive(Debug, Clone, TypedBuilder)]
+pub struct ServerConfig {
+    // used when no listen address is given
+    pub port: u16,
+    // tcp addresses, unix sockets or inherited sockets, `0.0.0.0:{port}` if empty
+    #[builder(default)]
+    pub listen: Vec<ListenAddr>,
+    // hard limits for every tenant and route
+    #[builder(default)]
+    pub limits: RequestLimits,
+    // serve `/metrics` without auth on these addresses, e.g. `127.0.0.1:9100`,
+    // it's only on the admin api otherwise
+    #[builder(default)]
+    pub metrics_listen: Vec<ListenAddr>,
+    // admin api is disabled unless configured
+    #[builder(default)]
+    pub admin: Option<AdminConfig>,
+    // https is disabled unless configured
+    #[builder(default)]
+    pub tls: Option<TlsConfig>,
+    // stops the server
+    #[builder(default)]
+    pub shutdown: ShutdownHandle,
+    // shut down on SIGINT and SIGTERM too, signal handling is process wide so
+    // it's left to embedders unless set
+    #[builder(default)]
+    pub handle_signals: bool,
+    // send the build hash in `x-dino-version` instead of `stable` / `canary`
+    #[builder(default)]
+    pub version_hash: bool,
+    // how long in-flight requests and `waitUntil` tasks get once shut down
+    #[builder(default = Duration::from_secs(30))]
+    pub drain_timeout: Duration,
+}
+
+impl FromStr for RouteMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.to_uppercase();
+        match s.as_str() {
+            "ANY" | "*" => Ok(RouteMethod::Any),
+            // standard methods and extension methods like PROPFIND
+            _ => Method::from_bytes(s.as_bytes())
+                .map(RouteMethod::Method)
+                .map_err(|_| format!("invalid method: {s}")),
+        }
+    }
+}
+
+impl fmt::Display for RouteMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RouteMethod::Any => write!(f, "ANY"),
+            RouteMethod::Method(m) => write!(f, "{m}"),
+        }
+    }
 }
 
 impl ProjectConfig {
-    pub fn load(filename: impl AsRef<Path>) -> Result<Self> {
+    /// Load config.yml, with the given profile merged over the base config.
+    pub fn load(filename: impl AsRef<Path>, profile: Option<&str>) -> Result<Self> {
+        let filename = filename.as_ref();
         let content = std::fs::read_to_string(filename)?;
-        let config: ProjectConfig = serde_yaml::from_str(&content)?;
-        Ok(config)
+        Ok(Self::parse(
+            &content,
+            &filename.display().to_string(),
+            profile,
+        )?)
+    }
+
+    /// Parse and validate config content, `file` is only used in error messages.
+    pub fn parse(content: &str, file: &str, profile: Option<&str>) -> Result<Self, ConfigError> {
+        let mut issues = validate_config(content, profile);
+        let ret = serde_yaml::from_str::<serde_yaml::Value>(content)
+            .map_err(|e| {
+                let (line, column) = e.location().map_or((1, 1), |v| (v.line(), v.column()));
+                ConfigIssue::new(line, column, yaml_error_message(&e))
+            })
+            .and_then(|mut value| {
+                apply_profile(&mut value, profile).map_err(|e| ConfigIssue::new(1, 1, e))?;
+                interpolate(&mut value, &env_var).map_err(|e| ConfigIssue::new(1, 1, e))?;
+                serde_path_to_error::deserialize::<_, ProjectConfig>(value).map_err(|e| {
+                    let (line, column) = locate_path(content, e.path(), profile).unwrap_or((1, 1));
+                    ConfigIssue::new(line, column, format!("{}: {}", e.path(), e.inner()))
+                })
+            });
+
+        match ret {
+            Ok(config) if issues.is_empty() => return Ok(config),
+            Ok(_) => {}
+            // the same problem is often found by both, keep the more precise one
+            Err(issue) => {
+                if !issues
+                    .iter()
+                    .any(|v| v.line == issue.line || v.message == issue.message)
+                {
+                    issues.push(issue);
+                }
+            }
+        }
+        issues.sort_by_key(|v| (v.line, v.column));
+        Err(ConfigError {
+            file: file.to_string(),
+            issues,
+        })
+    }
+
+    /// Js functions the routes call, handlers and proxy hooks.
+    pub fn handlers(&self) -> impl Iterator<Item = &str> {
+        self.routes
+            .values()
+            .flatten()
+            .filter_map(|route| match (&route.handler, &route.proxy) {
+                (Some(v), _) => Some(v.as_str()),
+                (_, Some(proxy)) => proxy.hook.as_deref(),
+                _ => None,
+            })
+    }
+
+    /// Schema files the routes refer to, as written in the config.
+    pub fn schema_files(&self) -> Vec<PathBuf> {
+        let mut files: Vec<_> = self
+            .routes
+            .values()
+            .flatten()
+            .flat_map(|route| route.schemas())
+            .filter_map(|v| v.as_ref()?.as_str().map(PathBuf::from))
+            .collect();
+        files.sort();
+        files.dedup();
+        files
+    }
+
+    /// Replace schema files with their content, read relative to `dir`, so
+    /// the config no longer depends on the directory it's served from.
+    pub fn inline_schemas(&mut self, dir: impl AsRef<Path>) -> Result<()> {
+        for route in self.routes.values_mut().flatten() {
+            for schema in route.schemas_mut().into_iter().flatten() {
+                if let serde_json::Value::String(path) = schema {
+                    *schema = load_schema_file(&dir.as_ref().join(&*path))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// JSON Schema of config.yml, for editor completion and validation.
+    pub fn schema() -> serde_json::Value {
+        let mut schema =
+            serde_json::to_value(schema_for!(ProjectConfig)).expect("schema is valid json");
+        // a profile may override any part of the base config
+        schema["definitions"]["Profile"] = json!({
+            "type": "object",
+            "additionalProperties": false,
+            "properties": schema["properties"].clone(),
+        });
+        schema["properties"]["profiles"] = json!({
+            "type": "object",
+            "additionalProperties": { "$ref": "#/definitions/Profile" },
+        });
+        schema
+    }
+}
+
+impl ProjectRoute {
+    fn schemas(&self) -> [&Option<serde_json::Value>; 4] {
+        [
+            &self.request_schema,
+            &self.query_schema,
+            &self.params_schema,
+            &self.response_schema,
+        ]
+    }
+
+    fn schemas_mut(&mut self) -> [&mut Option<serde_json::Value>; 4] {
+        [
+            &mut self.request_schema,
+            &mut self.query_schema,
+            &mut self.params_schema,
+            &mut self.response_schema,
+        ]
+    }
+
+    /// The single action configured for this route.
+    pub fn action(&self) -> Result<RouteAction, String> {
+        let mut actions = [
+            self.handler.clone().map(RouteAction::Handler),
+            self.redirect.clone().map(RouteAction::Redirect),
+            self.rewrite.clone().map(RouteAction::Rewrite),
+            self.respond.clone().map(RouteAction::Respond),
+            self.proxy.clone().map(RouteAction::Proxy),
+        ]
+        .into_iter()
+        .flatten();
+        let (Some(action), None) = (actions.next(), actions.next()) else {
+            return Err(format!(
+                "a route needs exactly one of {}",
+                ACTION_KEYS.join(", ")
+            ));
+        };
+
+        match &action {
+            RouteAction::Redirect(v) if !(300..400).contains(&v.status) => {
+                Err(format!("invalid redirect status: {}", v.status))
+            }
+            RouteAction::Redirect(v) if HeaderValue::from_str(&v.to).is_err() => {
+                Err(format!("invalid redirect target: {}", v.to))
+            }
+            RouteAction::Respond(v) if StatusCode::from_u16(v.status).is_err() => {
+                Err(format!("invalid status: {}", v.status))
+            }
+            RouteAction::Respond(v) => validate_headers(&v.headers).map(|_| action),
+            RouteAction::Proxy(v) => v.validate().map(|_| action),
+            _ => Ok(action),
+        }
+    }
+}
+
+// serde_yaml appends the location to the message, it's reported separately
+fn yaml_error_message(e: &serde_yaml::Error) -> String {
+    let msg = e.to_string();
+    match msg.rfind(" at line ") {
+        Some(idx) => msg[..idx].to_string(),
+        None => msg,
     }
 }
 
-fn deserialize_method<'de, D>(deserializer: D) -> Result<Method, D::Error>
+fn deserialize_methods<'de, D>(deserializer: D) -> Result<Vec<RouteMethod>, D::Error>
 where
     D: Deserializer<'de>,
 {
-    let s = String::deserialize(deserializer)?;
-    match s.to_uppercase().as_str() {
-        "GET" => Ok(Method::GET),
-        "POST" => Ok(Method::POST),
-        "PUT" => Ok(Method::PUT),
-        "DELETE" => Ok(Method::DELETE),
-        "PATCH" => Ok(Method::PATCH),
-        "HEAD" => Ok(Method::HEAD),
-        "OPTIONS" => Ok(Method::OPTIONS),
-        "CONNECT" => Ok(Method::CONNECT),
-        "TRACE" => Ok(Method::TRACE),
-        _ => Err(serde::de::Error::custom("invalid method")),
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Methods {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    let methods = match Methods::deserialize(deserializer)? {
+        Methods::One(v) => vec![v],
+        Methods::Many(v) => v,
+    };
+    if methods.is_empty() {
+        return Err(serde::de::Error::custom("at least one method is required"));
+    }
+    methods
+        .iter()
+        .map(|v| v.parse().map_err(serde::de::Error::custom))
+        .collect()
+}
+
+// sizes are either a number of bytes or a string like "10mb"
+pub(crate) fn size_schema(_gen: &mut SchemaGenerator) -> Schema {
+    SchemaObject {
+        instance_type: Some(vec![InstanceType::Integer, InstanceType::String].into()),
+        ..Default::default()
+    }
+    .into()
+}
+
+// durations are either a number of seconds or a string like "500ms"
+pub(crate) fn duration_schema(gen: &mut SchemaGenerator) -> Schema {
+    size_schema(gen)
+}
+
+fn methods_schema(gen: &mut SchemaGenerator) -> Schema {
+    SchemaObject {
+        subschemas: Some(Box::new(SubschemaValidation {
+            any_of: Some(vec![
+                gen.subschema_for::<String>(),
+                gen.subschema_for::<Vec<String>>(),
+            ]),
+            ..Default::default()
+        })),
+        ..Default::default()
+    }
+    .into()
+}
+
+/// Parse a size like "100", "512kb" or "10mb" into bytes.
+pub fn parse_size(s: &str) -> Result<usize, String> {
+    let s = s.trim().to_lowercase();
+    let (num, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+    let num: usize = num.parse().map_err(|_| format!("invalid size: {s}"))?;
+    let unit = match unit.trim() {
+        "" | "b" => 1,
+        "kb" | "k" => 1024,
+        "mb" | "m" => 1024 * 1024,
+        "gb" | "g" => 1024 * 1024 * 1024,
+        _ => return Err(format!("invalid size unit: {s}")),
+    };
+    num.checked_mul(unit)
+        .ok_or_else(|| format!("size too large: {s}"))
+}
+
+// accept either a plain number of bytes or a string like "512kb" / "10mb"
+pub(crate) fn deserialize_size<'de, D>(deserializer: D) -> Result<usize, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Size {
+        Bytes(usize),
+        Text(String),
+    }
+
+    match Size::deserialize(deserializer)? {
+        Size::Bytes(v) => Ok(v),
+        Size::Text(s) => parse_size(&s).map_err(serde::de::Error::custom),
+    }
+}
+
+// accept a number / bool or a string holding one, e.g. `status: ${STATUS}`,
+// variables are always substituted as strings
+pub(crate) fn deserialize_from_str<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + FromStr,
+    T::Err: fmt::Display,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Value<T> {
+        Plain(T),
+        Text(String),
+    }
+
+    match Value::<T>::deserialize(deserializer)? {
+        Value::Plain(v) => Ok(v),
+        Value::Text(s) => s
+            .trim()
+            .parse()
+            .map_err(|e| serde::de::Error::custom(format!("invalid value `{s}`: {e}"))),
+    }
+}
+
+pub(crate) fn deserialize_opt_from_str<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + FromStr,
+    T::Err: fmt::Display,
+{
+    deserialize_from_str(deserializer).map(Some)
+}
+
+// accept a number of seconds or a string like "500ms" / "5s" / "1m"
+pub(crate) fn deserialize_opt_duration<'de, D>(
+    deserializer: D,
+) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Value {
+        Secs(u64),
+        Text(String),
+    }
+
+    match Value::deserialize(deserializer)? {
+        Value::Secs(v) => Ok(Some(Duration::from_secs(v))),
+        Value::Text(s) => s
+            .trim()
+            .parse()
+            .map(Duration::from_secs)
+            .or_else(|_| humantime::parse_duration(&s))
+            .map(Some)
+            .map_err(|e| serde::de::Error::custom(format!("invalid duration `{s}`: {e}"))),
+    }
+}
+
+pub(crate) fn deserialize_opt_size<'de, D>(deserializer: D) -> Result<Option<usize>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_size(deserializer).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn project_config_body_limits_should_parse() {
+        let config: ProjectConfig = serde_yaml::from_str(
+            r#"
+name: test
+body:
+  json: 512kb
+  multipart: 20MB
+  raw: 100
+routes: {}
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.body.json, 512 * 1024);
+        assert_eq!(config.body.form, BodyLimits::default().form);
+        assert_eq!(config.body.multipart, 20 * 1024 * 1024);
+        assert_eq!(config.body.raw, 100);
+    }
+
+    #[test]
+    fn parse_size_should_reject_overflows() {
+        assert_eq!(parse_size("10mb"), Ok(10 * 1024 * 1024));
+        assert!(parse_size(&format!("{}gb", usize::MAX)).is_err());
+        assert!(parse_size(&format!("{}0", usize::MAX)).is_err());
+        let ret: Result<ProjectConfig, _> = serde_yaml::from_str(&format!(
+            "name: test\nbody:\n  json: {}kb\nroutes: {{}}\n",
+            usize::MAX
+        ));
+        assert!(ret.is_err());
+    }
+
+    #[test]
+    fn project_config_should_accept_substituted_strings() {
+        // what `${VAR}` values look like once interpolated
+        let config: ProjectConfig = serde_yaml::from_str(
+            r#"
+name: test
+limits:
+  headers: "64"
+matching:
+  case_insensitive: "true"
+routes:
+  /a:
+    - method: GET
+      redirect:
+        to: /b
+        status: "301"
+      timeout: "5"
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.limits.headers, Some(64));
+        assert!(config.matching.case_insensitive);
+        let route = &config.routes["/a"][0];
+        assert!(matches!(route.action(), Ok(RouteAction::Redirect(v)) if v.status == 301));
+        assert_eq!(route.options.timeout, Some(Duration::from_secs(5)));
+
+        let ret: Result<ProjectConfig, _> =
+            serde_yaml::from_str("name: test\nlimits:\n  headers: many\nroutes: {}\n");
+        assert!(ret.is_err());
+    }
+
+    #[test]
+    fn project_config_limits_should_parse() {
+        let config: ProjectConfig = serde_yaml::from_str(
+            r#"
+name: test
+limits:
+  body: 2mb
+  headers: 64
+routes:
+  /upload:
+    - method: POST
+      handler: upload
+      limits:
+        body: 100mb
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.limits.body, Some(2 * 1024 * 1024));
+        assert_eq!(config.limits.headers, Some(64));
+        assert_eq!(config.limits.url, None);
+        assert_eq!(
+            config.routes["/upload"][0].limits.body,
+            Some(100 * 1024 * 1024)
+        );
+    }
+
+    #[test]
+    fn project_route_options_should_parse() {
+        let config: ProjectConfig = serde_yaml::from_str(
+            r#"
+name: test
+routes:
+  /api/internal:
+    - method: POST
+      handler: internal
+      timeout: 500ms
+      auth: bearer
+      cache: 60
+      cors:
+        origins: ["https://example.com"]
+        credentials: true
+        max_age: 10m
+"#,
+        )
+        .unwrap();
+        let options = &config.routes["/api/internal"][0].options;
+        assert_eq!(options.timeout, Some(Duration::from_millis(500)));
+        assert_eq!(options.auth.as_deref(), Some("bearer"));
+        assert_eq!(options.cache, Some(Duration::from_secs(60)));
+        let cors = options.cors.as_ref().unwrap();
+        assert_eq!(cors.origins, ["https://example.com"]);
+        assert!(cors.credentials);
+        assert_eq!(cors.max_age, Some(Duration::from_secs(600)));
+    }
+
+    #[test]
+    fn project_route_methods_should_parse() {
+        let config: ProjectConfig = serde_yaml::from_str(
+            r#"
+name: test
+routes:
+  /a:
+    - method: get
+      handler: a
+  /b:
+    - method: [GET, post]
+      handler: b
+  /c:
+    - method: ANY
+      handler: c
+  /d:
+    - method: PROPFIND
+      handler: d
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.routes["/a"][0].methods,
+            [RouteMethod::Method(Method::GET)]
+        );
+        assert_eq!(
+            config.routes["/b"][0].methods,
+            [
+                RouteMethod::Method(Method::GET),
+                RouteMethod::Method(Method::POST)
+            ]
+        );
+        assert_eq!(config.routes["/c"][0].methods, [RouteMethod::Any]);
+        assert_eq!(
+            config.routes["/d"][0].methods,
+            [RouteMethod::Method(
+                Method::from_bytes(b"PROPFIND").unwrap()
+            )]
+        );
+
+        let ret: Result<ProjectConfig, _> = serde_yaml::from_str(
+            "name: test\nroutes:\n  /a:\n    - method: \"G ET\"\n      handler: a\n",
+        );
+        assert!(ret.is_err());
+    }
+
+    #[test]
+    fn project_route_action_should_reject_unsendable_headers() {
+        let config: ProjectConfig = serde_yaml::from_str(
+            r#"
+name: test
+routes:
+  /a:
+    - method: GET
+      respond:
+        headers:
+          x-ok: "1"
+  /b:
+    - method: GET
+      respond:
+        headers:
+          "bad name": "1"
+  /c:
+    - method: GET
+      redirect:
+        to: "/d\nx"
+"#,
+        )
+        .unwrap();
+        assert!(config.routes["/a"][0].action().is_ok());
+        assert!(config.routes["/b"][0].action().is_err());
+        assert!(config.routes["/c"][0].action().is_err());
+    }
+
+    #[test]
+    fn project_config_should_inline_schema_files() {
+        let mut config: ProjectConfig = serde_yaml::from_str(
+            r#"
+name: test
+routes:
+  /a:
+    - method: POST
+      handler: a
+      request_schema: user.schema.json
+      response_schema: user.schema.json
+      query_schema:
+        type: object
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.schema_files(), [PathBuf::from("user.schema.json")]);
+        config.inline_schemas("fixtures").unwrap();
+        assert!(config.schema_files().is_empty());
+        let route = &config.routes["/a"][0];
+        assert_eq!(route.request_schema.as_ref().unwrap()["type"], "object");
+        assert!(config.inline_schemas("missing").is_ok());
+
+        let mut config: ProjectConfig = serde_yaml::from_str(
+            "name: test\nroutes:\n  /a:\n    - method: GET\n      handler: a\n      query_schema: missing.json\n",
+        )
+        .unwrap();
+        assert!(config.inline_schemas("fixtures").is_err());
     }
 }
@@ -1,33 +1,65 @@
+use crate::{
+    body::{js_decode_utf8, js_parse_form},
+    RawBody, ServiceContext, ServiceReq,
+};
 use anyhow::Result;
-use axum::{body::Body, response::Response};
+use axum::{
+    body::Body,
+    http::{HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
 use dino_macros::{FromJs, IntoJs};
-use rquickjs::{Context, Function, Object, Promise, Runtime};
-use std::collections::HashMap;
+use indexmap::IndexMap;
+use rquickjs::{runtime::InterruptHandler, Context, Function, Object, Promise, Runtime, Value};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
 use typed_builder::TypedBuilder;
 
+const PRELUDE: &str = include_str!("prelude.js");
+
 #[allow(unused)]
 pub struct JsWorker {
     rt: Runtime,
     ctx: Context,
 }
 
-#[derive(Debug, TypedBuilder, IntoJs)]
+#[derive(Debug, Clone, TypedBuilder, IntoJs)]
 pub struct Req {
     #[builder(setter(into))]
     pub method: String,
     #[builder(setter(into))]
     pub url: String,
+    // the last value of repeated keys
     #[builder(default)]
     pub query: HashMap<String, String>,
+    // every value of each key, `req.queryAll` in js
+    #[builder(default)]
+    pub query_all: HashMap<String, Vec<String>>,
+    // undecoded query string without `?`, `req.rawQuery` in js
+    #[builder(default, setter(into))]
+    pub raw_query: String,
     #[builder(default)]
     pub params: HashMap<String, String>,
     #[builder(default)]
     pub headers: HashMap<String, String>,
     #[builder(default)]
     pub body: Option<String>,
+    // moved out of `req` by the prelude, backs `req.arrayBuffer()` / `req.formData()`
+    #[builder(default)]
+    pub raw_body: Option<RawBody>,
+    // part of the host matched by a `*.example.com` / `.example.com` tenant
+    #[builder(default)]
+    pub subdomain: Option<String>,
+    // `x-request-id` of the request, `req.requestId` in js
+    #[builder(default, setter(into))]
+    pub request_id: String,
 }
 
-#[derive(Debug, FromJs)]
+#[derive(Debug, FromJs, IntoJs)]
 pub struct Res {
     pub status: u16,
     pub headers: HashMap<String, String>,
@@ -40,7 +72,18 @@ fn print(msg: String) {
 
 impl JsWorker {
     pub fn try_new(module: &str) -> Result<Self> {
+        Self::try_new_with_interrupt(module, None, CancellationToken::new())
+    }
+
+    /// Like `try_new`, with the interrupt of `set_interrupt` already set while
+    /// the top-level code of the module runs.
+    pub fn try_new_with_interrupt(
+        module: &str,
+        timeout: Option<Duration>,
+        cancel: CancellationToken,
+    ) -> Result<Self> {
         let rt = Runtime::new()?;
+        rt.set_interrupt_handler(Some(interrupt_handler(timeout, cancel)));
         let ctx = Context::full(&rt)?;
 
         ctx.with(|ctx| {
@@ -50,6 +93,19 @@ impl JsWorker {
             // setup print function
             let fun = Function::new(ctx.clone(), print)?.with_name("print")?;
             global.set("print", fun)?;
+            // setup request body helpers
+            let fun = Function::new(ctx.clone(), js_parse_form)?.with_name("__dino_parse_form")?;
+            global.set("__dino_parse_form", fun)?;
+            let fun =
+                Function::new(ctx.clone(), js_decode_utf8)?.with_name("__dino_decode_utf8")?;
+            global.set("__dino_decode_utf8", fun)?;
+            let prelude: Object = ctx.eval(PRELUDE)?;
+            global.set("__dino_request", prelude.get::<_, Function>("request")?)?;
+            global.set("__dino_bind", prelude.get::<_, Function>("bind")?)?;
+            global.set("__dino_call", prelude.get::<_, Function>("call")?)?;
+            global.set("__dino_context", prelude.get::<_, Function>("context")?)?;
+            global.set("__dino_settle", prelude.get::<_, Function>("settle")?)?;
+            global.set("__dino_env", Object::new(ctx.clone())?)?;
 
             Ok::<_, anyhow::Error>(())
         })?;
@@ -57,29 +113,127 @@ impl JsWorker {
         Ok(Self { rt, ctx })
     }
 
+    /// Interrupt the js code once it runs longer than `timeout`.
+    pub fn set_timeout(&self, timeout: Duration) {
+        self.set_interrupt(Some(timeout), CancellationToken::new());
+    }
+
+    /// Interrupt the js code once it runs longer than `timeout`, if any, or
+    /// once `cancel` is cancelled. Replaces the previous timeout.
+    pub fn set_interrupt(&self, timeout: Option<Duration>, cancel: CancellationToken) {
+        self.rt
+            .set_interrupt_handler(Some(interrupt_handler(timeout, cancel)));
+    }
+
+    /// Expose service bindings (binding name => tenant host) to handlers as `env.NAME.fetch()`.
+    pub fn bind_services(
+        &self,
+        bindings: &IndexMap<String, String>,
+        services: ServiceContext,
+    ) -> Result<()> {
+        self.ctx.with(|ctx| {
+            let global = ctx.globals();
+            let fetch = Function::new(ctx.clone(), move |host: String, req: ServiceReq| {
+                services.fetch(&host, req)
+            })?
+            .with_name("__dino_fetch")?;
+            let hosts = Object::new(ctx.clone())?;
+            for (name, host) in bindings {
+                hosts.set(name.as_str(), host.as_str())?;
+            }
+            let bind: Function = global.get("__dino_bind")?;
+            let env: Object = bind.call((hosts, fetch))?;
+            global.set("__dino_env", env)?;
+
+            Ok::<_, anyhow::Error>(())
+        })
+    }
+
     pub fn run(&self, name: &str, req: Req) -> anyhow::Result<Res> {
         self.ctx.with(|ctx| {
             let global = ctx.globals();
             let handlers: Object = global.get("handlers")?;
             let fun: Function = handlers.get(name)?;
-            let v: Promise = fun.call((req,))?;
+            let request: Function = global.get("__dino_request")?;
+            let req: Value = request.call((req,))?;
+            let env: Object = global.get("__dino_env")?;
+            let context: Function = global.get("__dino_context")?;
+            let context: Object = context.call(())?;
+            let v: Promise = fun.call((req, env, context))?;
+
+            Ok::<_, anyhow::Error>(v.finish()?)
+        })
+    }
+
+    /// Run what handlers passed to `ctx.waitUntil()` to completion. A rejected
+    /// promise doesn't fail the others.
+    pub fn wait_until(&self) -> anyhow::Result<()> {
+        self.ctx.with(|ctx| {
+            let settle: Function = ctx.globals().get("__dino_settle")?;
+            let v: Promise = settle.call(())?;
+            v.finish::<Value>()?;
+            Ok::<_, anyhow::Error>(())
+        })
+    }
+
+    /// Whether the module exports a function called `name`.
+    pub fn has_handler(&self, name: &str) -> bool {
+        self.ctx.with(|ctx| {
+            let handlers: rquickjs::Result<Object> = ctx.globals().get("handlers");
+            handlers.is_ok_and(|v| v.get::<_, Function>(name).is_ok())
+        })
+    }
+
+    /// Bytes of js heap in use.
+    pub fn heap_size(&self) -> usize {
+        self.rt.memory_usage().memory_used_size as usize
+    }
+
+    /// Run a proxy hook, it returns the request to send upstream.
+    pub fn run_hook(&self, name: &str, req: Req) -> anyhow::Result<ServiceReq> {
+        self.ctx.with(|ctx| {
+            let global = ctx.globals();
+            let handlers: Object = global.get("handlers")?;
+            let fun: Function = handlers.get(name)?;
+            let request: Function = global.get("__dino_request")?;
+            let req: Value = request.call((req,))?;
+            let env: Object = global.get("__dino_env")?;
+            // hooks may be sync or async
+            let call: Function = global.get("__dino_call")?;
+            let v: Promise = call.call((fun, req, env))?;
 
             Ok::<_, anyhow::Error>(v.finish()?)
         })
     }
 }
 
+// handlers may return anything, what can't be sent is dropped instead of
+// panicking
+fn interrupt_handler(timeout: Option<Duration>, cancel: CancellationToken) -> InterruptHandler {
+    let deadline = timeout.map(|v| Instant::now() + v);
+    Box::new(move || cancel.is_cancelled() || deadline.is_some_and(|v| Instant::now() > v))
+}
+
 impl From<Res> for Response {
     fn from(res: Res) -> Self {
-        let mut builder = Response::builder().status(res.status);
+        let Ok(status) = StatusCode::from_u16(res.status) else {
+            warn!("handler returned an invalid status: {}", res.status);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        };
+        let mut ret = Response::new(res.body.map_or_else(Body::empty, Body::from));
+        *ret.status_mut() = status;
         for (k, v) in res.headers {
-            builder = builder.header(k, v);
-        }
-        if let Some(body) = res.body {
-            builder.body(body.into()).unwrap()
-        } else {
-            builder.body(Body::empty()).unwrap()
+            match (
+                HeaderName::from_bytes(k.as_bytes()),
+                HeaderValue::from_str(&v),
+            ) {
+                (Ok(k), Ok(v)) => {
+                    ret.headers_mut().append(k, v);
+                }
+                _ => warn!("handler returned an invalid header: {}", k),
+            }
         }
+        ret
     }
 }
 
@@ -107,9 +261,150 @@ mod tests {
             .method("GET")
             .url("https://example.com")
             .headers(HashMap::new())
+            .request_id("abc-123")
             .build();
         let worker = JsWorker::try_new(code).unwrap();
         let ret = worker.run("hello", req).unwrap();
         assert_eq!(ret.status, 200);
+        assert!(ret.body.unwrap().contains(r#""requestId":"abc-123""#));
+    }
+
+    #[test]
+    fn js_worker_should_parse_body() {
+        let code = r#"
+    (function(){
+        async function hello(req){
+            const data = await req.json();
+            return { status: 200, headers: {}, body: data.name };
+        }
+        async function upload(req){
+            const form = await req.formData();
+            const file = form.get("file");
+            const text = await file.text();
+            return { status: 200, headers: {}, body: `${form.get("title")}:${file.name}:${text}` };
+        }
+        return{hello:hello,upload:upload};
+    })();
+    "#;
+        let worker = JsWorker::try_new(code).unwrap();
+
+        let req = Req::builder()
+            .method("POST")
+            .url("https://example.com")
+            .headers(HashMap::from([(
+                "content-type".to_string(),
+                "application/json".to_string(),
+            )]))
+            .body(Some(r#"{"name":"dino"}"#.to_string()))
+            .build();
+        let ret = worker.run("hello", req).unwrap();
+        assert_eq!(ret.body.as_deref(), Some("dino"));
+
+        let body = "--b\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\nhi\r\n\
+            --b\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\r\nabc\r\n--b--";
+        let req = Req::builder()
+            .method("POST")
+            .url("https://example.com")
+            .headers(HashMap::from([(
+                "content-type".to_string(),
+                "multipart/form-data; boundary=b".to_string(),
+            )]))
+            .raw_body(Some(RawBody(body.into())))
+            .build();
+        let ret = worker.run("upload", req).unwrap();
+        assert_eq!(ret.body.as_deref(), Some("hi:a.txt:abc"));
+    }
+
+    #[test]
+    fn js_worker_should_expose_repeated_query_keys() {
+        let code = r#"
+    (function(){
+        async function search(req){
+            const body = `${req.query.tag}:${req.queryAll.tag.join(",")}:${req.rawQuery}`;
+            return { status: 200, headers: {}, body };
+        }
+        return{search:search};
+    })();
+    "#;
+        let worker = JsWorker::try_new(code).unwrap();
+        let query = crate::QueryString::parse(Some("tag=a&tag=b"));
+        let req = Req::builder()
+            .method("GET")
+            .url("https://example.com/search?tag=a&tag=b")
+            .query(query.last())
+            .query_all(query.all)
+            .raw_query(query.raw)
+            .build();
+        let ret = worker.run("search", req).unwrap();
+        assert_eq!(ret.body.as_deref(), Some("b:a,b:tag=a&tag=b"));
+    }
+
+    #[test]
+    fn js_worker_should_run_wait_until_after_response() {
+        let code = r#"
+    (function(){
+        let done = [];
+        async function hello(req, env, ctx){
+            ctx.waitUntil(Promise.resolve().then(() => done.push("a")));
+            ctx.waitUntil(Promise.reject(new Error("ignored")));
+            ctx.waitUntil((async () => { await null; done.push("b"); })());
+            return { status: 200, headers: {}, body: "hi" };
+        }
+        async function check(req){
+            return { status: 200, headers: {}, body: done.join(",") };
+        }
+        return{hello:hello,check:check};
+    })();
+    "#;
+        let worker = JsWorker::try_new(code).unwrap();
+        let req = || Req::builder().method("GET").url("/").build();
+        let ret = worker.run("hello", req()).unwrap();
+        assert_eq!(ret.body.as_deref(), Some("hi"));
+        worker.wait_until().unwrap();
+        let ret = worker.run("check", req()).unwrap();
+        assert_eq!(ret.body.as_deref(), Some("a,b"));
+    }
+
+    #[test]
+    fn js_worker_should_be_interrupted_once_cancelled() {
+        let code = r#"
+    (function(){
+        async function spin(req){
+            while (true) {}
+        }
+        return{spin:spin};
+    })();
+    "#;
+        let worker = JsWorker::try_new(code).unwrap();
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        worker.set_interrupt(None, cancel);
+        let req = Req::builder().method("GET").url("/").build();
+        assert!(worker.run("spin", req).is_err());
+    }
+
+    #[test]
+    fn res_into_response_should_drop_what_cant_be_sent() {
+        let res = Res {
+            status: 201,
+            headers: HashMap::from([
+                ("x-ok".to_string(), "1".to_string()),
+                ("bad name".to_string(), "1".to_string()),
+                ("x-bad".to_string(), "a\nb".to_string()),
+            ]),
+            body: None,
+        };
+        let res = Response::from(res);
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(res.headers().len(), 1);
+        assert_eq!(res.headers()["x-ok"], "1");
+
+        let res = Res {
+            status: 1000,
+            headers: HashMap::new(),
+            body: Some("hi".to_string()),
+        };
+        let res = Response::from(res);
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
     }
 }
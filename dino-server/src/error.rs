@@ -1,7 +1,10 @@
+use crate::Violation;
 use axum::{
-    http::{Method, StatusCode},
+    http::{header::ALLOW, HeaderValue, Method, StatusCode},
     response::{IntoResponse, Response},
+    Json,
 };
+use serde_json::json;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -12,8 +15,58 @@ pub enum AppError {
     #[error("Path not found: {0}")]
     RoutePathNotFound(String),
 
+    // the second field lists the methods allowed on the path
     #[error("Method not found: {0}")]
-    RouteMethodNotAllowed(Method),
+    RouteMethodNotAllowed(Method, Vec<Method>),
+
+    #[error("Payload too large: limit is {0} bytes")]
+    PayloadTooLarge(usize),
+
+    #[error("URI too long: limit is {0} bytes")]
+    UriTooLong(usize),
+
+    #[error("Request header fields too large: limit is {0} headers")]
+    TooManyHeaders(usize),
+
+    #[error("Handler timed out")]
+    HandlerTimeout,
+
+    #[error("Invalid service request: {0}")]
+    InvalidServiceRequest(String),
+
+    #[error("Service call depth exceeded when calling: {0}")]
+    ServiceDepthExceeded(String),
+
+    #[error("Too many rewrites for: {0}")]
+    TooManyRewrites(String),
+
+    #[error("Invalid rewrite target: {0}")]
+    InvalidRewrite(String),
+
+    #[error("Bad gateway: {0}")]
+    BadGateway(String),
+
+    #[error("Upstream timed out: {0}")]
+    UpstreamTimeout(String),
+
+    #[error("Invalid path param: {0}")]
+    InvalidPathParam(String),
+
+    #[error("Invalid config: {0}")]
+    InvalidConfig(String),
+
+    #[error("Invalid code: {0}")]
+    InvalidCode(String),
+
+    #[error("Host already exists: {0}")]
+    HostExists(String),
+
+    #[error("Version not found: {0}")]
+    VersionNotFound(String),
+
+    // every violation is listed in the response
+    #[error("Invalid request: {} violations", .0.len())]
+    InvalidRequest(Vec<Violation>),
 
     #[error("Anyhow error: {0}")]
     Anyhow(#[from] anyhow::Error),
@@ -22,16 +75,62 @@ pub enum AppError {
     Serde(#[from] serde_json::Error),
 }
 
-impl IntoResponse for AppError {
-    fn into_response(self) -> Response {
-        let code = match self {
+impl AppError {
+    pub fn status(&self) -> StatusCode {
+        match self {
             AppError::HostNotFound(_) => StatusCode::NOT_FOUND,
             AppError::RoutePathNotFound(_) => StatusCode::NOT_FOUND,
-            AppError::RouteMethodNotAllowed(_) => StatusCode::METHOD_NOT_ALLOWED,
+            AppError::RouteMethodNotAllowed(..) => StatusCode::METHOD_NOT_ALLOWED,
+            AppError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::UriTooLong(_) => StatusCode::URI_TOO_LONG,
+            AppError::TooManyHeaders(_) => StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+            AppError::HandlerTimeout => StatusCode::GATEWAY_TIMEOUT,
+            AppError::InvalidServiceRequest(_) => StatusCode::BAD_REQUEST,
+            AppError::ServiceDepthExceeded(_) => StatusCode::LOOP_DETECTED,
+            AppError::TooManyRewrites(_) => StatusCode::LOOP_DETECTED,
+            AppError::InvalidRewrite(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::BadGateway(_) => StatusCode::BAD_GATEWAY,
+            AppError::UpstreamTimeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            AppError::InvalidPathParam(_) => StatusCode::BAD_REQUEST,
+            AppError::InvalidConfig(_) => StatusCode::BAD_REQUEST,
+            AppError::InvalidCode(_) => StatusCode::BAD_REQUEST,
+            AppError::HostExists(_) => StatusCode::CONFLICT,
+            AppError::VersionNotFound(_) => StatusCode::NOT_FOUND,
+            AppError::InvalidRequest(_) => StatusCode::BAD_REQUEST,
             AppError::Anyhow(_) => StatusCode::INTERNAL_SERVER_ERROR,
             AppError::Serde(_) => StatusCode::INTERNAL_SERVER_ERROR,
-        };
+        }
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        if let AppError::InvalidRequest(violations) = &self {
+            let body = json!({ "error": "invalid request", "violations": violations });
+            return (self.status(), Json(body)).into_response();
+        }
+        let mut res = (self.status(), self.to_string()).into_response();
+        if let AppError::RouteMethodNotAllowed(_, allowed) = &self {
+            res.headers_mut().insert(ALLOW, allow_header(allowed));
+        }
+        res
+    }
+}
+
+pub(crate) fn allow_header(methods: &[Method]) -> HeaderValue {
+    let methods: Vec<&str> = methods.iter().map(|m| m.as_str()).collect();
+    HeaderValue::from_str(&methods.join(", ")).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        (code, self.to_string()).into_response()
+    #[test]
+    fn method_not_allowed_should_have_allow_header() {
+        let err = AppError::RouteMethodNotAllowed(Method::PUT, vec![Method::GET, Method::HEAD]);
+        let res = err.into_response();
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(res.headers()[ALLOW], "GET, HEAD");
     }
 }
@@ -0,0 +1,79 @@
+use crate::AppRouterInner;
+use serde::{Deserialize, Serialize};
+use std::{collections::VecDeque, sync::Arc, time::SystemTime};
+
+/// Number of versions kept for rollback, the current one included.
+pub const MAX_VERSIONS: usize = 10;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VersionInfo {
+    pub hash: String,
+    // where the version came from, e.g. "cli" or "admin"
+    pub source: String,
+    // rfc3339, e.g. "2024-06-01T08:00:00Z"
+    pub created_at: String,
+    #[serde(default)]
+    pub current: bool,
+}
+
+struct Version {
+    info: VersionInfo,
+    router: Arc<AppRouterInner>,
+}
+
+// versions are kept newest first
+#[derive(Default)]
+pub(crate) struct History {
+    versions: VecDeque<Version>,
+}
+
+impl History {
+    pub(crate) fn push(&mut self, router: Arc<AppRouterInner>, source: &str) -> VersionInfo {
+        let info = VersionInfo {
+            hash: router.hash.clone(),
+            source: source.to_string(),
+            created_at: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+            current: false,
+        };
+        self.versions.push_front(Version {
+            info: info.clone(),
+            router,
+        });
+        self.versions.truncate(MAX_VERSIONS);
+        VersionInfo {
+            current: true,
+            ..info
+        }
+    }
+
+    pub(crate) fn list(&self, current: &Arc<AppRouterInner>) -> Vec<VersionInfo> {
+        self.versions
+            .iter()
+            .map(|v| VersionInfo {
+                current: Arc::ptr_eq(&v.router, current),
+                ..v.info.clone()
+            })
+            .collect()
+    }
+
+    /// The version with the given hash, or the one before the current version.
+    pub(crate) fn find(
+        &self,
+        to: Option<&str>,
+        current: &Arc<AppRouterInner>,
+    ) -> Option<(Arc<AppRouterInner>, VersionInfo)> {
+        let version = match to {
+            Some(hash) => self.versions.iter().find(|v| v.info.hash == hash),
+            None => self
+                .versions
+                .iter()
+                .skip_while(|v| !Arc::ptr_eq(&v.router, current))
+                .nth(1),
+        }?;
+        let info = VersionInfo {
+            current: true,
+            ..version.info.clone()
+        };
+        Some((version.router.clone(), info))
+    }
+}
@@ -0,0 +1,322 @@
+use crate::{AppError, SwappableAppRouter};
+use anyhow::anyhow;
+use axum::http::Uri;
+use dashmap::{mapref::entry::Entry, DashMap};
+use std::sync::{Arc, RwLock};
+use tracing::info;
+
+/// Tenant registered under this host serves every host nothing else matches.
+pub const DEFAULT_HOST: &str = "*";
+
+/// Tenants by host, see `tenant_key`. Keys with a host pattern are also
+/// kept aside, most specific first, so that exact hosts never need a scan.
+#[derive(Clone, Default)]
+pub struct TenantRouters {
+    routers: Arc<DashMap<String, SwappableAppRouter>>,
+    patterns: Arc<RwLock<Vec<String>>>,
+}
+
+impl From<DashMap<String, SwappableAppRouter>> for TenantRouters {
+    fn from(routers: DashMap<String, SwappableAppRouter>) -> Self {
+        let ret = Self {
+            routers: Arc::new(routers),
+            patterns: Default::default(),
+        };
+        ret.sync_patterns();
+        ret
+    }
+}
+
+impl TenantRouters {
+    pub fn get(&self, key: &str) -> Option<SwappableAppRouter> {
+        self.routers.get(key).map(|v| v.value().clone())
+    }
+
+    pub fn insert(&self, key: String, router: SwappableAppRouter) -> Option<SwappableAppRouter> {
+        let ret = self.routers.insert(key, router);
+        self.sync_patterns();
+        ret
+    }
+
+    pub fn remove(&self, key: &str) -> Option<SwappableAppRouter> {
+        let ret = self.routers.remove(key).map(|(_, v)| v);
+        self.sync_patterns();
+        ret
+    }
+
+    /// Update the entry of `key` while it's locked, e.g. to only add a
+    /// tenant if it doesn't exist yet.
+    pub fn update<T>(
+        &self,
+        key: String,
+        f: impl FnOnce(Entry<String, SwappableAppRouter>) -> T,
+    ) -> T
+    where
+        T: 'static,
+    {
+        let ret = f(self.routers.entry(key));
+        self.sync_patterns();
+        ret
+    }
+
+    /// Every tenant with its key, sorted by key.
+    pub fn entries(&self) -> Vec<(String, SwappableAppRouter)> {
+        let mut ret: Vec<_> = self
+            .routers
+            .iter()
+            .map(|v| (v.key().clone(), v.value().clone()))
+            .collect();
+        ret.sort_by(|a, b| a.0.cmp(&b.0));
+        ret
+    }
+
+    // the list is rebuilt under its lock, so the last change always wins
+    fn sync_patterns(&self) {
+        let mut patterns = self.patterns.write().unwrap();
+        *patterns = self
+            .routers
+            .iter()
+            .map(|v| v.key().clone())
+            .filter(|k| is_pattern(split_mount(k).0))
+            .collect();
+        patterns.sort_by(|a, b| specificity(b).cmp(&specificity(a)).then(a.cmp(b)));
+    }
+}
+
+/// The tenant serving a request.
+pub(crate) struct Tenant {
+    // the host and base path it was registered with
+    pub key: String,
+    pub router: SwappableAppRouter,
+    // part of the host matched by a pattern
+    pub subdomain: Option<String>,
+    // path prefix the tenant is mounted under, e.g. `/billing`
+    pub base_path: Option<String>,
+}
+
+impl Tenant {
+    /// `uri` as seen by the tenant, i.e. without its base path.
+    pub fn unmount(&self, uri: &Uri) -> Result<Uri, AppError> {
+        let Some(base) = &self.base_path else {
+            return Ok(uri.clone());
+        };
+        let path = uri.path().get(base.len()..).unwrap_or_default();
+        let path = if path.is_empty() { "/" } else { path };
+        let uri = match uri.query() {
+            Some(q) => format!("{path}?{q}"),
+            None => path.to_string(),
+        };
+        Ok(uri.parse().map_err(|e| anyhow!("{e}"))?)
+    }
+}
+
+/// Tenants are registered by host, optionally followed by the path prefix
+/// they are mounted under, e.g. `example.com/billing`. Hosts are lowercased,
+/// paths keep their case but lose any trailing slash.
+pub(crate) fn tenant_key(key: &str) -> String {
+    let (host, base) = split_mount(key);
+    format!("{}{}", host.to_lowercase(), base.trim_end_matches('/'))
+}
+
+/// Find the tenant for a host and path: exact hosts first, then the longest
+/// matching pattern, then the default tenant. Patterns are either wildcards
+/// like `*.preview.example.com` (subdomains only) or suffixes like
+/// `.example.com` (the domain itself and its subdomains). Among the tenants of
+/// the same host, the longest base path containing `path` wins.
+pub(crate) fn get_router_by_host(
+    host: String,
+    path: &str,
+    routers: &TenantRouters,
+) -> Result<Tenant, AppError> {
+    let host = strip_port(&host).to_lowercase();
+    info!("host: {:?}", host);
+
+    // exact hosts are looked up directly, longest base path first
+    let bases = path.rmatch_indices('/').map(|(idx, _)| &path[..idx]);
+    for base in std::iter::once(path).chain(bases) {
+        let key = format!("{host}{base}");
+        if let Some(router) = routers.get(&key) {
+            return Ok(Tenant {
+                key,
+                router,
+                subdomain: None,
+                base_path: (!base.is_empty()).then(|| base.to_string()),
+            });
+        }
+    }
+
+    // then the first matching pattern, they're sorted most specific first
+    let patterns = routers.patterns.read().unwrap();
+    for key in patterns.iter() {
+        let (pattern, base) = split_mount(key);
+        if !is_mounted(base, path) {
+            continue;
+        }
+        let subdomain = match pattern {
+            DEFAULT_HOST => None,
+            _ => match match_pattern(pattern, &host) {
+                Some(v) => v,
+                None => continue,
+            },
+        };
+        // a tenant removed since the list was read is skipped
+        if let Some(router) = routers.get(key) {
+            return Ok(Tenant {
+                key: key.clone(),
+                router,
+                subdomain,
+                base_path: (!base.is_empty()).then(|| base.to_string()),
+            });
+        }
+    }
+    Err(AppError::HostNotFound(host))
+}
+
+// patterns rank above the default host, then longer patterns and base paths
+// above shorter ones
+fn specificity(key: &str) -> (bool, usize, usize) {
+    let (pattern, base) = split_mount(key);
+    (pattern != DEFAULT_HOST, pattern.len(), base.len())
+}
+
+fn is_pattern(host: &str) -> bool {
+    host == DEFAULT_HOST || host.starts_with("*.") || host.starts_with('.')
+}
+
+// `example.com/billing` => (`example.com`, `/billing`)
+fn split_mount(key: &str) -> (&str, &str) {
+    key.find('/').map_or((key, ""), |idx| key.split_at(idx))
+}
+
+// `/billing` contains `/billing` and `/billing/...`, but not `/billings`
+fn is_mounted(base: &str, path: &str) -> bool {
+    match path.strip_prefix(base) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/') || base.is_empty(),
+        None => false,
+    }
+}
+
+// returns the captured subdomain if `host` matches the pattern
+pub(crate) fn match_pattern(pattern: &str, host: &str) -> Option<Option<String>> {
+    if let Some(suffix) = pattern.strip_prefix("*.") {
+        let sub = host.strip_suffix(suffix)?.strip_suffix('.')?;
+        return (!sub.is_empty()).then(|| Some(sub.to_string()));
+    }
+    if let Some(domain) = pattern.strip_prefix('.') {
+        if host == domain {
+            return Some(None);
+        }
+        let sub = host.strip_suffix(pattern)?;
+        return (!sub.is_empty()).then(|| Some(sub.to_string()));
+    }
+    None
+}
+
+pub(crate) fn strip_port(host: &str) -> &str {
+    // ipv6 literals carry colons of their own, e.g. `[::1]:8080`
+    if host.starts_with('[') {
+        return host.find(']').map_or(host, |idx| &host[..=idx]);
+    }
+    host.split(':').next().unwrap_or(host)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ProjectConfig;
+
+    // tenants are told apart by their code
+    fn router(code: &str) -> SwappableAppRouter {
+        let config: ProjectConfig = serde_yaml::from_str("name: test\nroutes: {}").unwrap();
+        SwappableAppRouter::try_new(code, config).unwrap()
+    }
+
+    fn name(ret: Result<Tenant, AppError>) -> (String, Option<String>) {
+        let tenant = ret.unwrap();
+        (tenant.router.load().code.clone(), tenant.subdomain)
+    }
+
+    #[test]
+    fn match_pattern_should_work() {
+        let pattern = "*.preview.example.com";
+        assert_eq!(
+            match_pattern(pattern, "pr-12.preview.example.com"),
+            Some(Some("pr-12".to_string()))
+        );
+        assert_eq!(match_pattern(pattern, "preview.example.com"), None);
+        assert_eq!(match_pattern(pattern, "xpreview.example.com"), None);
+
+        assert_eq!(match_pattern(".example.com", "example.com"), Some(None));
+        assert_eq!(
+            match_pattern(".example.com", "a.b.example.com"),
+            Some(Some("a.b".to_string()))
+        );
+        assert_eq!(match_pattern(".example.com", "badexample.com"), None);
+        assert_eq!(match_pattern("example.com", "a.example.com"), None);
+    }
+
+    #[test]
+    fn get_router_by_host_should_prefer_exact_hosts() {
+        let routers = TenantRouters::default();
+        routers.insert("api.example.com".to_string(), router("api"));
+        routers.insert(".example.com".to_string(), router("site"));
+        routers.insert("*.preview.example.com".to_string(), router("preview"));
+
+        let ret = get_router_by_host("API.example.com:8080".to_string(), "/", &routers);
+        assert_eq!(name(ret), ("api".to_string(), None));
+        let ret = get_router_by_host("pr-1.preview.example.com".to_string(), "/", &routers);
+        assert_eq!(name(ret), ("preview".to_string(), Some("pr-1".to_string())));
+        let ret = get_router_by_host("www.example.com".to_string(), "/", &routers);
+        assert_eq!(name(ret), ("site".to_string(), Some("www".to_string())));
+        assert!(matches!(
+            get_router_by_host("localhost".to_string(), "/", &routers),
+            Err(AppError::HostNotFound(_))
+        ));
+
+        routers.insert(DEFAULT_HOST.to_string(), router("default"));
+        let ret = get_router_by_host("[::1]:3000".to_string(), "/", &routers);
+        assert_eq!(name(ret), ("default".to_string(), None));
+
+        routers.remove(".example.com");
+        let ret = get_router_by_host("www.example.com".to_string(), "/", &routers);
+        assert_eq!(name(ret), ("default".to_string(), None));
+    }
+
+    #[test]
+    fn get_router_by_host_should_prefer_longest_base_path() {
+        let routers = TenantRouters::default();
+        for key in [
+            "example.com",
+            "Example.com/billing/",
+            "example.com/billing/v2",
+        ] {
+            routers.insert(tenant_key(key), router(key));
+        }
+        routers.insert(tenant_key("*/status"), router("status"));
+
+        let base = |path: &str| {
+            let tenant = get_router_by_host("example.com".to_string(), path, &routers).unwrap();
+            (tenant.router.load().code.clone(), tenant.base_path)
+        };
+        assert_eq!(base("/"), ("example.com".to_string(), None));
+        assert_eq!(base("/billings"), ("example.com".to_string(), None));
+        assert_eq!(
+            base("/billing"),
+            (
+                "Example.com/billing/".to_string(),
+                Some("/billing".to_string())
+            )
+        );
+        assert_eq!(
+            base("/billing/v2/invoices"),
+            (
+                "example.com/billing/v2".to_string(),
+                Some("/billing/v2".to_string())
+            )
+        );
+        // mounted default tenants serve any host
+        let tenant = get_router_by_host("other.com".to_string(), "/status", &routers).unwrap();
+        assert_eq!(tenant.base_path.as_deref(), Some("/status"));
+        assert!(get_router_by_host("other.com".to_string(), "/", &routers).is_err());
+    }
+}
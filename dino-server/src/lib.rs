@@ -1,88 +1,572 @@
+mod actions;
+mod admin;
+mod assets;
+mod body;
+mod canary;
 mod config;
 mod engine;
 mod error;
+mod history;
+mod host;
+mod limits;
+mod listen;
+mod metrics;
 mod middleware;
+mod openapi;
+mod profile;
+mod proxy;
+mod query;
 mod router;
+mod service;
+mod shadow;
+mod shutdown;
+mod tls;
+mod validate;
+mod validator;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use axum::{
-    body::Bytes,
-    extract::{Host, Query, State},
-    http::{request::Parts, Response},
-    response::IntoResponse,
+    body::{Body, Bytes},
+    extract::{Host, Request, State},
+    http::{
+        header::{ACCESS_CONTROL_REQUEST_METHOD, ALLOW, CONTENT_LENGTH, CONTENT_TYPE},
+        request::Parts,
+        HeaderValue, Method, StatusCode,
+    },
+    response::{IntoResponse, Redirect, Response},
     routing::any,
-    Router,
+    Json, Router,
 };
 use dashmap::DashMap;
 use indexmap::IndexMap;
-use matchit::Match;
-use middleware::ServerTimeLayer;
-use std::collections::HashMap;
-use tokio::net::TcpListener;
-use tracing::info;
+use middleware::{call_route, MetricsLayer, RequestIdLayer, ServerTimeLayer, REQUEST_ID_HEADER};
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    future::{ready, Ready},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{sync::oneshot, task::JoinSet};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tower::util::{service_fn, ServiceFn};
+use tracing::{info, warn, Span};
 
+pub use actions::*;
+pub use admin::*;
+pub use assets::*;
+pub use body::*;
+pub use canary::{CanaryRule, CanaryStatus, HeaderRule, Selection, VERSION_HEADER};
 pub use config::*;
 pub use engine::*;
+use error::allow_header;
 pub use error::AppError;
+pub use history::{VersionInfo, MAX_VERSIONS};
+use host::{get_router_by_host, tenant_key};
+pub use host::{TenantRouters, DEFAULT_HOST};
+pub use limits::*;
+pub use listen::ListenAddr;
+use listen::{ConnInfo, Listener};
+use metrics::{metrics, Labels, RequestLabels};
+pub use metrics::{metrics_router, render_metrics, METRICS_CONTENT_TYPE};
+pub use openapi::{openapi_document, OpenApi, OpenApiConfig};
+pub use profile::*;
+pub use proxy::{proxy_request, ProxyConfig};
+pub use query::QueryString;
 pub use router::*;
+pub use service::*;
+use shadow::Shadow;
+pub use shadow::{ShadowRule, ShadowStatus};
+pub use shutdown::ShutdownHandle;
+use shutdown::{drain, watch_signals};
+use tls::{redirect_router, tls_acceptor, watch_certs, CertResolver};
+pub use tls::{CertFiles, TlsConfig};
+pub use validate::{validate_config, ConfigError, ConfigIssue};
+pub use validator::{load_schema, load_schema_file, RequestValidator, Violation};
 
 type ProjectRoutes = IndexMap<String, Vec<ProjectRoute>>;
 
+// upper bound of the `waitUntil` work of a request
+const WAIT_UNTIL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Request header carrying the base path of a mounted tenant.
+pub const FORWARDED_PREFIX: &str = "x-forwarded-prefix";
+
+/// Request header carrying the scheme of the connection, `http` or `https`.
+pub const FORWARDED_PROTO: &str = "x-forwarded-proto";
+
 #[derive(Clone)]
 pub struct AppState {
     // key is hostname
-    routers: DashMap<String, SwappableAppRouter>,
+    routers: TenantRouters,
+    limits: RequestLimits,
+    // work that outlives its response, waited for on shutdown
+    tasks: TaskTracker,
+    shutdown: ShutdownHandle,
+    // tell clients which build served them, not just whether it's the canary
+    version_hash: bool,
 }
 
 #[derive(Clone)]
 pub struct TenentRouter {
+    // exact host, `*.example.com` / `.example.com` pattern, or DEFAULT_HOST,
+    // optionally followed by a base path, e.g. `example.com/billing`
     host: String,
+    // other hosts served by the same router
+    aliases: Vec<String>,
     router: SwappableAppRouter,
+    // served over https for the host and its aliases
+    cert: Option<CertFiles>,
 }
 
-pub async fn start_server(port: u16, routers: Vec<TenentRouter>) -> Result<()> {
-    let addr = format!("0.0.0.0:{port}");
-    let listener = TcpListener::bind(addr).await?;
-
-    info!("listening on {}", listener.local_addr()?);
+pub async fn start_server(config: ServerConfig, routers: Vec<TenentRouter>) -> Result<()> {
+    let all = |port| Some(ListenAddr::Tcp(format!("0.0.0.0:{port}")));
+    let listeners = bind(&config.listen, all(config.port), "http").await?;
 
     let map = DashMap::new();
-    for TenentRouter { host, router } in routers {
-        map.insert(host, router);
+    let mut certs = vec![];
+    for TenentRouter {
+        host,
+        aliases,
+        router,
+        cert,
+    } in routers
+    {
+        for alias in aliases {
+            let key = tenant_key(&alias);
+            certs.extend(cert_for(&key, &cert));
+            map.insert(key, router.clone());
+        }
+        let key = tenant_key(&host);
+        certs.extend(cert_for(&key, &cert));
+        map.insert(key, router);
+    }
+    let shutdown = config.shutdown.clone();
+    let state = AppState {
+        shutdown: shutdown.clone(),
+        version_hash: config.version_hash,
+        ..AppState::new(map, config.limits)
+    };
+    let (routers, tasks) = (state.routers.clone(), state.tasks.clone());
+    if config.handle_signals {
+        tokio::spawn(watch_signals(shutdown.clone()));
     }
-    let state = AppState::new(map);
     let app = Router::new()
         .route("/*path", any(handler))
         .layer(ServerTimeLayer)
+        .layer(MetricsLayer)
+        // outermost, so every other layer runs in the request span and sees the id
+        .layer(RequestIdLayer)
         .with_state(state);
 
-    axum::serve(listener, app.into_make_service()).await?;
-    Ok(())
+    let mut servers = JoinSet::new();
+    let plain = match &config.tls {
+        Some(tls) => {
+            let resolver = Arc::new(CertResolver::try_new(certs, tls.default_cert.clone())?);
+            tokio::spawn(watch_certs(resolver.clone(), tls.reload_interval));
+            let acceptor = tls_acceptor(resolver)?;
+            for listener in bind(&tls.listen, all(tls.port), "https").await? {
+                let (app, acceptor) = (app.clone(), Some(acceptor.clone()));
+                servers.spawn(listen::serve(listener, app, acceptor, shutdown.clone()));
+            }
+            match tls.redirect_http {
+                true => redirect_router(tls.port),
+                false => app,
+            }
+        }
+        None => app,
+    };
+    for listener in listeners {
+        servers.spawn(listen::serve(
+            listener,
+            plain.clone(),
+            None,
+            shutdown.clone(),
+        ));
+    }
+    for listener in bind(&config.metrics_listen, None, "metrics").await? {
+        let app = metrics_router(routers.clone());
+        servers.spawn(listen::serve(listener, app, None, shutdown.clone()));
+    }
+    if let Some(admin) = config.admin {
+        let local = ListenAddr::Tcp(format!("127.0.0.1:{}", admin.port));
+        let app = admin_router(routers, &admin.token);
+        for listener in bind(&admin.listen, Some(local), "the admin api").await? {
+            servers.spawn(listen::serve(listener, app.clone(), None, shutdown.clone()));
+        }
+    }
+
+    // the first server to fail stops them all
+    let running = async {
+        while let Some(ret) = servers.join_next().await {
+            ret??;
+        }
+        Ok(())
+    };
+    tokio::pin!(running);
+    tokio::select! {
+        ret = &mut running => ret,
+        _ = shutdown.wait() => drain(running, &tasks, config.drain_timeout, &shutdown).await,
+    }
+}
+
+// `default`, if any, unless addresses are given
+async fn bind(
+    addrs: &[ListenAddr],
+    default: Option<ListenAddr>,
+    name: &str,
+) -> Result<Vec<Listener>> {
+    let listeners = match (addrs.is_empty(), default) {
+        (true, Some(default)) => Listener::bind_all(&[default]).await?,
+        _ => Listener::bind_all(addrs).await?,
+    };
+    for listener in &listeners {
+        info!("listening for {} on {}", name, listener.local_addr());
+    }
+    Ok(listeners)
+}
+
+// certificates are picked by host, mount paths don't matter
+fn cert_for(key: &str, cert: &Option<CertFiles>) -> Option<(String, CertFiles)> {
+    let host = key.split('/').next().unwrap_or(key);
+    let cert = cert.clone().filter(|_| host != DEFAULT_HOST)?;
+    Some((host.to_string(), cert))
 }
 
 // we only support JSON requests and return JSON responses
 #[allow(unused)]
 async fn handler(
     State(state): State<AppState>,
-    parts: Parts,
-    Host(mut host): Host,
-    Query(query): Query<HashMap<String, String>>,
-    body: Option<Bytes>,
-) -> Result<impl IntoResponse, AppError> {
-    let router = get_router_by_host(host, state)?;
-    let matched = router.match_it(parts.method.clone(), parts.uri.path())?;
-    let req = assemble_req(&matched, &parts, query, body)?;
-    let handler = matched.value;
+    mut parts: Parts,
+    Host(host): Host,
+    body: Body,
+) -> Result<Response, AppError> {
+    // server limits are checked before anything else
+    state.limits.check(&parts)?;
+    let tenant = get_router_by_host(host, parts.uri.path(), &state.routers)?;
+    if let Some(labels) = parts.extensions.get::<RequestLabels>() {
+        labels.set_tenant(&tenant.key);
+    }
+    // handlers of a mounted tenant see paths relative to its base path
+    parts.uri = tenant.unmount(&parts.uri)?;
+    // only the server says where a tenant is mounted and how it was reached
+    parts.headers.remove(FORWARDED_PREFIX);
+    let conn = parts.extensions.get::<ConnInfo>().copied();
+    let proto = ConnInfo::scheme(conn.as_ref());
+    parts
+        .headers
+        .insert(FORWARDED_PROTO, HeaderValue::from_static(proto));
+    if let Some(v) = tenant
+        .base_path
+        .as_ref()
+        .and_then(|v| HeaderValue::from_str(v).ok())
+    {
+        parts.headers.insert(FORWARDED_PREFIX, v);
+    }
+    // a canary may serve the request instead of the current version
+    let selection = tenant.router.select(&parts.headers);
+    let router = selection.router.clone();
+    let shadow = tenant.router.sample_shadow(&parts.method);
+    let (subdomain, base) = (tenant.subdomain, tenant.base_path);
+    let version_hash = state.version_hash;
+    let mut res = serve(state, router, shadow, subdomain, base, parts, body)
+        .await
+        .into_response();
+    let secure = conn.is_some_and(|v| v.secure);
+    selection.apply(&mut res, secure, version_hash);
+    Ok(res)
+}
+
+async fn serve(
+    state: AppState,
+    router: AppRouter,
+    shadow: Option<Arc<Shadow>>,
+    subdomain: Option<String>,
+    base_path: Option<String>,
+    mut parts: Parts,
+    body: Body,
+) -> Result<Response, AppError> {
+    if let Some(openapi) = router
+        .openapi
+        .as_ref()
+        .filter(|v| parts.method == Method::GET && parts.uri.path() == v.path)
+    {
+        router.limits.check(&parts)?;
+        return Ok(Json(&openapi.document).into_response());
+    }
+    // static assets are only served for GET / HEAD
+    let assets = router
+        .assets
+        .as_ref()
+        .filter(|_| matches!(parts.method, Method::GET | Method::HEAD));
+    if let Some(res) = assets.and_then(|v| v.serve_first(&parts)) {
+        router.limits.check(&parts)?;
+        return Ok(res);
+    }
+    // the path routes see, or a redirect if only its trailing slash is off
+    let (mut route, redirect) = router.route_path(parts.uri.path());
+    if let Some(path) = redirect {
+        router.limits.check(&parts)?;
+        let base = base_path.as_deref().unwrap_or_default();
+        let location = match parts.uri.query() {
+            Some(q) => format!("{base}{path}?{q}"),
+            None => format!("{base}{path}"),
+        };
+        return Ok(Redirect::permanent(&location).into_response());
+    }
+    // rewrites are dispatched as if the target was requested
+    if let Some(target) = router.rewrite(&parts.method, &route)? {
+        let uri = match (target.contains('?'), parts.uri.query()) {
+            (false, Some(q)) => format!("{target}?{q}"),
+            _ => target.clone(),
+        };
+        parts.uri = uri.parse().map_err(|_| AppError::InvalidRewrite(target))?;
+        route = router.route_path(parts.uri.path()).0;
+    }
+    let matched = match router.match_it(parts.method.clone(), &route) {
+        Ok(v) => v,
+        // OPTIONS is answered automatically unless the project handles it
+        Err(AppError::RouteMethodNotAllowed(Method::OPTIONS, allowed)) => {
+            router.limits.check(&parts)?;
+            // a CORS preflight is answered with the policy of the method it asks for
+            let cors = parts
+                .headers
+                .get(ACCESS_CONTROL_REQUEST_METHOD)
+                .and_then(|v| Method::from_bytes(v.as_bytes()).ok())
+                .and_then(|m| router.match_it(m, &route).ok())
+                .and_then(|m| m.value.options.cors.clone());
+            let service = fixed(move || {
+                (StatusCode::NO_CONTENT, [(ALLOW, allow_header(&allowed))]).into_response()
+            });
+            let options = RouteOptions {
+                cors,
+                ..Default::default()
+            };
+            return call_route(&options, service, Request::from_parts(parts, body)).await;
+        }
+        // assets only fill paths no route claims, a method mismatch stays a 405
+        Err(e @ AppError::RoutePathNotFound(_)) => {
+            router.limits.check(&parts)?;
+            return assets.and_then(|v| v.serve_fallback(&parts)).ok_or(e);
+        }
+        Err(e) => {
+            router.limits.check(&parts)?;
+            return Err(e);
+        }
+    };
+    let limits = matched.value.limits.or(&router.limits);
+    limits.check(&parts)?;
+    let content_type = parts
+        .headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok());
+    let limit = limits
+        .body
+        .unwrap_or_else(|| router.body_limits.limit_for(content_type));
+    let limit = state.limits.body.map_or(limit, |v| v.min(limit));
+
+    let endpoint = matched.value.clone();
+    let labels = parts.extensions.get::<RequestLabels>().cloned();
+    if let Some(labels) = &labels {
+        labels.set_route(&endpoint.path);
+    }
+    let labels = labels.map(|v| v.get()).unwrap_or_default();
+    let params = request_params(&matched, parts.uri.path());
+    let query = QueryString::parse(parts.uri.query());
+    // service calls carry the id of the request that made them
+    let request_id = parts
+        .headers
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    let services = ServiceContext::new(state.routers.clone())
+        .with_request_id(request_id)
+        .with_deadline(endpoint.options.timeout, state.shutdown.deadline());
+    let req = Request::from_parts(parts, body);
+    let method = req.method().clone();
+    match endpoint.action {
+        RouteAction::Handler(handler) => {
+            let invocation = Invocation {
+                router: router.clone(),
+                services,
+                handler,
+                params,
+                query,
+                subdomain,
+                shadow,
+                validator: endpoint.validator.clone(),
+                body_limit: limit,
+                timeout: endpoint.options.timeout,
+                tasks: state.tasks.clone(),
+                deadline: state.shutdown.deadline(),
+                labels: labels.clone(),
+            };
+            let service = service_fn(move |req: Request| {
+                let invocation = invocation.clone();
+                async move { Ok::<_, Infallible>(invoke(invocation, req).await.into_response()) }
+            });
+            let ret = call_route(&endpoint.options, service, req).await;
+            if let Err(AppError::HandlerTimeout) = ret {
+                let m = &metrics().timeouts;
+                m.with_label_values(&[&labels.tenant, &labels.route]).inc();
+            }
+            ret
+        }
+        // the other actions are answered without calling js
+        RouteAction::Redirect(redirect) => {
+            let service = fixed(move || into_response(redirect.res(&params), &method));
+            call_route(&endpoint.options, service, req).await
+        }
+        RouteAction::Respond(respond) => {
+            let service = fixed(move || into_response(respond.res(), &method));
+            call_route(&endpoint.options, service, req).await
+        }
+        RouteAction::Proxy(proxy) => {
+            let router = router.clone();
+            let timeout = endpoint.options.timeout;
+            let service = service_fn(move |req: Request| {
+                let (proxy, router, params) = (proxy.clone(), router.clone(), params.clone());
+                let services = services.clone();
+                async move {
+                    let res =
+                        proxy_request(&proxy, &router, services, &params, req, limit, timeout)
+                            .await;
+                    Ok::<_, Infallible>(res.into_response())
+                }
+            });
+            call_route(&endpoint.options, service, req).await
+        }
+        RouteAction::Rewrite(_) => Err(AppError::TooManyRewrites(req.uri().path().to_string())),
+    }
+}
+
+// a service that always answers the same way
+fn fixed<F>(f: F) -> ServiceFn<impl FnMut(Request) -> Ready<Result<Response, Infallible>>>
+where
+    F: Fn() -> Response + Send + 'static,
+{
+    service_fn(move |_req: Request| ready(Ok::<_, Infallible>(f())))
+}
+
+// HEAD responses keep the headers of GET, but never a body
+fn into_response(res: Res, method: &Method) -> Response {
+    let len = res.body.as_ref().map_or(0, |v| v.len());
+    let mut res = Response::from(res);
+    if method == Method::HEAD {
+        res.headers_mut()
+            .entry(CONTENT_LENGTH)
+            .or_insert(len.into());
+        *res.body_mut() = Body::empty();
+    }
+    res
+}
+
+// everything needed to call a js handler once the route is matched
+#[derive(Clone)]
+struct Invocation {
+    router: AppRouter,
+    services: ServiceContext,
+    handler: String,
+    params: HashMap<String, String>,
+    query: QueryString,
+    subdomain: Option<String>,
+    // mirrors the request once the live response is known
+    shadow: Option<Arc<Shadow>>,
+    validator: Option<Arc<RequestValidator>>,
+    body_limit: usize,
+    timeout: Option<Duration>,
+    tasks: TaskTracker,
+    // interrupts the worker once the server is done draining
+    deadline: CancellationToken,
+    labels: Labels,
+}
+
+async fn invoke(invocation: Invocation, req: Request) -> Result<Response, AppError> {
+    let Invocation {
+        router,
+        services,
+        handler,
+        params,
+        query,
+        subdomain,
+        shadow,
+        validator,
+        body_limit,
+        timeout,
+        tasks,
+        deadline,
+        labels,
+    } = invocation;
+    let (parts, body) = req.into_parts();
+    let body = read_body(body, body_limit).await?;
+    if let Some(validator) = validator {
+        validator.validate(&params, &query, &body)?;
+    }
+    let req = assemble_req(&parts, params, query, subdomain, body)?;
     // TODO: build a worker pool, and send req via mpsc channel and get res from oneshot channel
     // but if code changed we need to recreate the worker pool
-    let worker = JsWorker::try_new(&router.code)?;
-    let res = worker.run(handler, req)?;
-    Ok(Response::from(res))
+    // js runs on a blocking thread so that route timeouts can fire
+    let mirror = shadow.map(|v| (v, req.clone(), deadline.clone()));
+    let (tx, rx) = oneshot::channel();
+    // logs of the worker belong to the request too
+    let span = Span::current();
+    tasks.spawn_blocking(move || {
+        let _span = span.enter();
+        let m = metrics();
+        let _busy = m.busy_worker();
+        let worker = JsWorker::try_new_with_interrupt(&router.code, timeout, deadline.clone())
+            .and_then(|worker| {
+                worker.bind_services(&router.services, services)?;
+                Ok(worker)
+            });
+        let worker = match worker {
+            Ok(v) => v,
+            Err(e) => {
+                m.exceptions
+                    .with_label_values(&[&labels.tenant, &labels.route])
+                    .inc();
+                let _ = tx.send(Err(e));
+                return;
+            }
+        };
+        let ret = worker.run(&handler, req);
+        // a closed channel means the route timed out, it's counted as such
+        if ret.is_err() && !tx.is_closed() {
+            m.exceptions
+                .with_label_values(&[&labels.tenant, &labels.route])
+                .inc();
+        }
+        m.js_heap
+            .with_label_values(&[&labels.tenant])
+            .observe(worker.heap_size() as f64);
+        let _ = tx.send(ret);
+        // `ctx.waitUntil()` work goes on once the response is sent, for a while
+        worker.set_interrupt(Some(WAIT_UNTIL_TIMEOUT), deadline);
+        if let Err(e) = worker.wait_until() {
+            warn!("waitUntil of {} failed: {:#}", handler, e);
+        }
+    });
+    let ret = rx.await.map_err(|e| anyhow!(e))?;
+
+    if let Some((shadow, req, deadline)) = mirror {
+        let live = match &ret {
+            Ok(res) => (res.status, res.body.clone()),
+            Err(_) => (StatusCode::INTERNAL_SERVER_ERROR.as_u16(), None),
+        };
+        tasks.spawn(shadow.mirror(req, live, timeout, deadline));
+    }
+    Ok(into_response(ret?, &parts.method))
 }
 
 impl AppState {
-    pub fn new(routers: DashMap<String, SwappableAppRouter>) -> Self {
-        Self { routers }
+    pub fn new(routers: DashMap<String, SwappableAppRouter>, limits: RequestLimits) -> Self {
+        Self {
+            routers: routers.into(),
+            limits,
+            tasks: TaskTracker::new(),
+            shutdown: ShutdownHandle::new(),
+            version_hash: false,
+        }
     }
 }
 
@@ -90,50 +574,67 @@ impl TenentRouter {
     pub fn new(host: impl Into<String>, router: SwappableAppRouter) -> Self {
         Self {
             host: host.into(),
+            aliases: vec![],
             router,
+            cert: None,
         }
     }
-}
 
-fn get_router_by_host(mut host: String, state: AppState) -> Result<AppRouter, AppError> {
-    let _ = host.split_off(host.find(':').unwrap_or(host.len()));
-    info!("host: {:?}", host);
-
-    let router: AppRouter = state
-        .routers
-        .get(&host)
-        .ok_or(AppError::HostNotFound(host))?
-        .load();
+    /// Serve the host and its aliases with this certificate over https.
+    /// Certificates are only read when the server starts, tenants added
+    /// through the admin api can't bring their own.
+    pub fn with_cert(mut self, cert: CertFiles) -> Self {
+        self.cert = Some(cert);
+        self
+    }
 
-    Ok(router)
+    /// Serve another host with the same router.
+    pub fn with_alias(mut self, host: impl Into<String>) -> Self {
+        self.aliases.push(host.into());
+        self
+    }
 }
 
 fn assemble_req(
-    matched: &Match<&str>,
     parts: &Parts,
-    query: HashMap<String, String>,
-    body: Option<Bytes>,
+    params: HashMap<String, String>,
+    query: QueryString,
+    subdomain: Option<String>,
+    body: Bytes,
 ) -> Result<Req, AppError> {
-    let params: HashMap<String, String> = matched
-        .params
-        .iter()
-        .map(|(k, v)| (k.to_string(), v.to_string()))
-        .collect();
     // convert request data into Req
     let headers = parts
         .headers
         .iter()
         .map(|(k, v)| (k.to_string(), v.to_str().unwrap().to_string()))
         .collect();
-    let body = body.and_then(|v| String::from_utf8(v.into()).ok());
+    let (body, raw_body) = if body.is_empty() {
+        (None, None)
+    } else {
+        (
+            Some(String::from_utf8_lossy(&body).into_owned()),
+            Some(RawBody(body)),
+        )
+    };
 
+    // set by `RequestIdLayer`
+    let request_id = parts
+        .headers
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
     let req = Req::builder()
         .method(parts.method.to_string())
         .url(parts.uri.to_string())
-        .query(query)
+        .query(query.last())
+        .query_all(query.all)
+        .raw_query(query.raw)
         .params(params)
         .headers(headers)
         .body(body)
+        .raw_body(raw_body)
+        .subdomain(subdomain)
+        .request_id(request_id)
         .build();
 
     Ok(req)
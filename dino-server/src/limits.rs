@@ -0,0 +1,119 @@
+use crate::{
+    config::{deserialize_opt_from_str, deserialize_opt_size, size_schema},
+    AppError,
+};
+use axum::http::{header::CONTENT_LENGTH, request::Parts};
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+/// Limits on incoming requests. They can be set for the whole server, for a
+/// tenant (`limits:` in config.yml) or for a single route; route limits
+/// override tenant limits, server limits are always enforced.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct RequestLimits {
+    // max body size in bytes, replaces the per content type `body:` limits when set
+    #[serde(default, deserialize_with = "deserialize_opt_size")]
+    #[schemars(schema_with = "size_schema")]
+    pub body: Option<usize>,
+    // max number of header fields
+    #[serde(default, deserialize_with = "deserialize_opt_from_str")]
+    pub headers: Option<usize>,
+    // max length of the request target (path and query)
+    #[serde(default, deserialize_with = "deserialize_opt_size")]
+    #[schemars(schema_with = "size_schema")]
+    pub url: Option<usize>,
+}
+
+impl RequestLimits {
+    /// Fill unset limits from the outer level.
+    pub fn or(&self, outer: &RequestLimits) -> RequestLimits {
+        RequestLimits {
+            body: self.body.or(outer.body),
+            headers: self.headers.or(outer.headers),
+            url: self.url.or(outer.url),
+        }
+    }
+
+    /// Check everything that is known before reading the body.
+    pub fn check(&self, parts: &Parts) -> Result<(), AppError> {
+        if let Some(max) = self.url {
+            let len = parts.uri.path_and_query().map_or(0, |v| v.as_str().len());
+            if len > max {
+                return Err(AppError::UriTooLong(max));
+            }
+        }
+
+        if let Some(max) = self.headers {
+            if parts.headers.len() > max {
+                return Err(AppError::TooManyHeaders(max));
+            }
+        }
+
+        if let Some(max) = self.body {
+            let len = parts
+                .headers
+                .get(CONTENT_LENGTH)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse::<usize>().ok());
+            if len.is_some_and(|v| v > max) {
+                return Err(AppError::PayloadTooLarge(max));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Request;
+
+    fn parts(uri: &str, headers: &[(&str, &str)]) -> Parts {
+        let mut builder = Request::builder().uri(uri);
+        for (k, v) in headers {
+            builder = builder.header(*k, *v);
+        }
+        builder.body(()).unwrap().into_parts().0
+    }
+
+    #[test]
+    fn request_limits_should_merge() {
+        let route = RequestLimits {
+            body: Some(100),
+            ..Default::default()
+        };
+        let tenant = RequestLimits {
+            body: Some(10),
+            headers: Some(20),
+            url: None,
+        };
+        let limits = route.or(&tenant);
+        assert_eq!(limits.body, Some(100));
+        assert_eq!(limits.headers, Some(20));
+        assert_eq!(limits.url, None);
+    }
+
+    #[test]
+    fn request_limits_should_check() {
+        let limits = RequestLimits {
+            body: Some(10),
+            headers: Some(2),
+            url: Some(16),
+        };
+        assert!(limits.check(&parts("/api/hello", &[("a", "1")])).is_ok());
+        assert!(matches!(
+            limits.check(&parts("/api/hello?name=world", &[])),
+            Err(AppError::UriTooLong(16))
+        ));
+        assert!(matches!(
+            limits.check(&parts("/", &[("a", "1"), ("b", "2"), ("c", "3")])),
+            Err(AppError::TooManyHeaders(2))
+        ));
+        assert!(matches!(
+            limits.check(&parts("/", &[("content-length", "2147483648")])),
+            Err(AppError::PayloadTooLarge(10))
+        ));
+    }
+}
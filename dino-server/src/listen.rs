@@ -0,0 +1,497 @@
+use crate::ShutdownHandle;
+use anyhow::{anyhow, Result};
+use axum::{Extension, Router};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto,
+    service::TowerToHyperService,
+};
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
+use std::{fmt, io, net::SocketAddr, path::PathBuf, str::FromStr, time::Duration};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, TcpStream},
+};
+use tokio_rustls::TlsAcceptor;
+use tokio_util::task::TaskTracker;
+use tower::Layer;
+use tracing::warn;
+
+#[cfg(unix)]
+use std::{
+    collections::HashSet,
+    env, fs,
+    os::{
+        fd::{BorrowedFd, FromRawFd, OwnedFd, RawFd},
+        unix::fs::FileTypeExt,
+    },
+    path::Path,
+    sync::Mutex,
+};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+
+// retrying right away after e.g. EMFILE would spin
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_secs(1);
+// clients that never finish the handshake would hold the connection forever
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// getsockopt(SO_TYPE) fails with ENOTSOCK for anything but a socket, and
+// SO_ACCEPTCONN tells if it's listening where it's supported
+#[cfg(unix)]
+fn is_listening(socket: SockRef) -> bool {
+    let listening = socket.r#type().is_ok_and(|v| v == Type::STREAM);
+    #[cfg(any(
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "fuchsia",
+        target_os = "linux"
+    ))]
+    let listening = listening && socket.is_listener().unwrap_or(false);
+    listening
+}
+
+// systemd passes sockets starting from this fd
+#[cfg(unix)]
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Where a server accepts connections.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenAddr {
+    // `0.0.0.0:3000`, `[::1]:3000` or `localhost:3000`, every resolved address
+    // is bound and ipv6 addresses only accept ipv6
+    Tcp(String),
+    // `unix:/run/dino.sock`
+    Unix(PathBuf),
+    // `fd:3`, a listening socket inherited from the parent process
+    Fd(i32),
+    // `systemd`, every socket passed with `LISTEN_FDS` / `LISTEN_PID`
+    Systemd,
+}
+
+/// The connection a request came in on, set as a request extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ConnInfo {
+    // tcp peer, none for unix sockets
+    pub peer: Option<SocketAddr>,
+    // over tls
+    pub secure: bool,
+}
+
+impl ConnInfo {
+    /// Scheme of a request made over a connection, if any.
+    pub fn scheme(conn: Option<&Self>) -> &'static str {
+        match conn.is_some_and(|v| v.secure) {
+            true => "https",
+            false => "http",
+        }
+    }
+}
+
+pub(crate) enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+enum Conn {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+// inherited fds are owned once taken, they must never be taken twice
+#[cfg(unix)]
+static TAKEN_FDS: Mutex<Option<HashSet<RawFd>>> = Mutex::new(None);
+
+impl FromStr for ListenAddr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "systemd" {
+            return Ok(Self::Systemd);
+        }
+        if let Some(path) = s.strip_prefix("unix:") {
+            return Ok(Self::Unix(path.into()));
+        }
+        if let Some(fd) = s.strip_prefix("fd:") {
+            return fd
+                .parse()
+                .map(Self::Fd)
+                .map_err(|_| format!("invalid fd {fd}"));
+        }
+        match s.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {
+                Ok(Self::Tcp(s.to_string()))
+            }
+            _ => Err(format!(
+                "invalid listen address {s}, expected host:port, unix:PATH, fd:N or systemd"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(v) => write!(f, "{v}"),
+            Self::Unix(v) => write!(f, "unix:{}", v.display()),
+            Self::Fd(v) => write!(f, "fd:{v}"),
+            Self::Systemd => write!(f, "systemd"),
+        }
+    }
+}
+
+impl Listener {
+    /// Bind every address, the first failure fails them all.
+    pub(crate) async fn bind_all(addrs: &[ListenAddr]) -> Result<Vec<Self>> {
+        let mut listeners = vec![];
+        for addr in addrs {
+            match addr {
+                ListenAddr::Tcp(v) => {
+                    let mut resolved: Vec<_> = tokio::net::lookup_host(v).await?.collect();
+                    resolved.dedup();
+                    for addr in resolved {
+                        listeners.push(Self::Tcp(bind_tcp(addr)?));
+                    }
+                }
+                #[cfg(unix)]
+                ListenAddr::Unix(path) => listeners.push(bind_unix(path)?),
+                #[cfg(unix)]
+                ListenAddr::Fd(fd) => listeners.push(Self::from_fd(*fd)?),
+                #[cfg(unix)]
+                ListenAddr::Systemd => {
+                    let fds = systemd_fds();
+                    if fds.is_empty() {
+                        return Err(anyhow!("no sockets were passed with LISTEN_FDS"));
+                    }
+                    for fd in fds {
+                        listeners.push(Self::from_fd(fd)?);
+                    }
+                }
+                #[cfg(not(unix))]
+                addr => return Err(anyhow!("{addr} is only supported on unix")),
+            }
+        }
+        Ok(listeners)
+    }
+
+    /// Take over a listening socket, tcp or unix, inherited from the parent.
+    #[cfg(unix)]
+    pub(crate) fn from_fd(fd: RawFd) -> Result<Self> {
+        let mut taken = TAKEN_FDS.lock().unwrap();
+        let taken = taken.get_or_insert_with(HashSet::new);
+        if taken.contains(&fd) {
+            return Err(anyhow!("fd {fd} is already in use"));
+        }
+        // SAFETY: only borrowed to ask the kernel what it is, which fails for
+        // closed fds. Ownership is only taken once it's a listening socket
+        let borrowed = unsafe { BorrowedFd::borrow_raw(fd) };
+        if !is_listening(SockRef::from(&borrowed)) {
+            return Err(anyhow!("fd {fd} is not a listening socket"));
+        }
+        taken.insert(fd);
+        // SAFETY: the fd is inherited and, as checked above, nothing else owns it
+        let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+        let unix = std::os::unix::net::UnixListener::from(socket);
+        // the address of a tcp socket isn't a unix one
+        if unix.local_addr().is_ok() {
+            unix.set_nonblocking(true)?;
+            return Ok(Self::Unix(UnixListener::from_std(unix)?));
+        }
+        let tcp = std::net::TcpListener::from(OwnedFd::from(unix));
+        tcp.set_nonblocking(true)?;
+        Ok(Self::Tcp(TcpListener::from_std(tcp)?))
+    }
+
+    pub(crate) fn local_addr(&self) -> String {
+        let addr = match self {
+            Self::Tcp(v) => v.local_addr().map(|v| v.to_string()),
+            #[cfg(unix)]
+            Self::Unix(v) => v.local_addr().map(|v| match v.as_pathname() {
+                Some(path) => format!("unix:{}", path.display()),
+                None => "unix".to_string(),
+            }),
+        };
+        addr.unwrap_or_else(|_| "unknown".to_string())
+    }
+
+    // unix peers have no address worth forwarding
+    async fn accept(&self) -> std::io::Result<(Conn, Option<SocketAddr>)> {
+        match self {
+            Self::Tcp(v) => {
+                let (stream, addr) = v.accept().await?;
+                Ok((Conn::Tcp(stream), Some(addr)))
+            }
+            #[cfg(unix)]
+            Self::Unix(v) => {
+                let (stream, _) = v.accept().await?;
+                Ok((Conn::Unix(stream), None))
+            }
+        }
+    }
+}
+
+/// Accept connections and serve them with `app`, over http/1.1 or h2, after
+/// a tls handshake if there is an acceptor. Once shut down, no connection is
+/// accepted and open ones are drained.
+pub(crate) async fn serve(
+    listener: Listener,
+    app: Router,
+    tls: Option<TlsAcceptor>,
+    shutdown: ShutdownHandle,
+) -> Result<()> {
+    let connections = TaskTracker::new();
+    loop {
+        let accepted = tokio::select! {
+            ret = listener.accept() => ret,
+            _ = shutdown.wait() => break,
+        };
+        let (conn, peer) = match accepted {
+            Ok(v) => v,
+            // the client went away before it was accepted
+            Err(e) if is_connection_error(&e) => continue,
+            // e.g. out of fds, retrying right away would spin
+            Err(e) => {
+                warn!("failed to accept connection: {}", e);
+                tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                continue;
+            }
+        };
+        let info = ConnInfo {
+            peer,
+            secure: tls.is_some(),
+        };
+        let (app, tls, shutdown) = (app.clone(), tls.clone(), shutdown.clone());
+        let name = peer.map_or_else(|| listener.local_addr(), |v| v.to_string());
+        connections.spawn(async move {
+            match conn {
+                Conn::Tcp(v) => serve_io(v, app, tls, shutdown, info, &name).await,
+                #[cfg(unix)]
+                Conn::Unix(v) => serve_io(v, app, tls, shutdown, info, &name).await,
+            }
+        });
+    }
+    connections.close();
+    connections.wait().await;
+    Ok(())
+}
+
+async fn serve_io<I>(
+    io: I,
+    app: Router,
+    tls: Option<TlsAcceptor>,
+    shutdown: ShutdownHandle,
+    info: ConnInfo,
+    peer: &str,
+) where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let Some(acceptor) = tls else {
+        return serve_connection(io, app, shutdown, info, peer).await;
+    };
+    match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(io)).await {
+        Ok(Ok(v)) => serve_connection(v, app, shutdown, info, peer).await,
+        Ok(Err(e)) => warn!("tls handshake with {} failed: {}", peer, e),
+        Err(_) => warn!("tls handshake with {} timed out", peer),
+    }
+}
+
+fn is_connection_error(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+    )
+}
+
+async fn serve_connection<I>(
+    io: I,
+    app: Router,
+    shutdown: ShutdownHandle,
+    info: ConnInfo,
+    peer: &str,
+) where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    // every request of the connection gets to know where it came from
+    let service = TowerToHyperService::new(Extension(info).layer(app));
+    let builder = auto::Builder::new(TokioExecutor::new());
+    let conn = builder.serve_connection_with_upgrades(TokioIo::new(io), service);
+    tokio::pin!(conn);
+    let ret = tokio::select! {
+        ret = conn.as_mut() => ret,
+        _ = shutdown.wait() => {
+            conn.as_mut().graceful_shutdown();
+            conn.await
+        }
+    };
+    if let Err(e) = ret {
+        warn!("connection with {} failed: {}", peer, e);
+    }
+}
+
+// ipv6 sockets don't take ipv4 too, so `0.0.0.0` and `[::]` can both be bound
+fn bind_tcp(addr: SocketAddr) -> Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    Ok(TcpListener::from_std(socket.into())?)
+}
+
+#[cfg(unix)]
+fn bind_unix(path: &Path) -> Result<Listener> {
+    if let Ok(meta) = fs::symlink_metadata(path) {
+        if !meta.file_type().is_socket() {
+            return Err(anyhow!("{} exists and isn't a socket", path.display()));
+        }
+        // a socket left behind by a previous run fails the bind, one that is
+        // still served is left alone
+        if std::os::unix::net::UnixStream::connect(path).is_ok() {
+            return Err(anyhow!("{} is in use", path.display()));
+        }
+        fs::remove_file(path)?;
+    }
+    Ok(Listener::Unix(UnixListener::bind(path)?))
+}
+
+// the environment is left as is, it's not safe to change once threads run:
+// children get another pid so they ignore the variables, and `TAKEN_FDS`
+// keeps the sockets from being taken twice
+#[cfg(unix)]
+fn systemd_fds() -> Vec<RawFd> {
+    let pid = env::var("LISTEN_PID")
+        .ok()
+        .and_then(|v| v.parse::<u32>().ok());
+    let count = env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|v| v.parse::<RawFd>().ok());
+    match (pid, count) {
+        (Some(pid), Some(count)) if pid == std::process::id() => {
+            (LISTEN_FDS_START..LISTEN_FDS_START + count).collect()
+        }
+        _ => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    const REQ: &str = "GET / HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n";
+
+    fn app() -> Router {
+        Router::new().route("/", get(|| async { "hello" }))
+    }
+
+    async fn get_hello<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S) -> String {
+        stream.write_all(REQ.as_bytes()).await.unwrap();
+        let mut res = String::new();
+        stream.read_to_string(&mut res).await.ok();
+        res
+    }
+
+    #[test]
+    fn listen_addr_should_parse() {
+        let parse = |s: &str| s.parse::<ListenAddr>();
+        assert_eq!(
+            parse("[::1]:3000"),
+            Ok(ListenAddr::Tcp("[::1]:3000".into()))
+        );
+        assert_eq!(
+            parse("localhost:80"),
+            Ok(ListenAddr::Tcp("localhost:80".into()))
+        );
+        assert_eq!(
+            parse("unix:/run/dino.sock"),
+            Ok(ListenAddr::Unix("/run/dino.sock".into()))
+        );
+        assert_eq!(parse("fd:3"), Ok(ListenAddr::Fd(3)));
+        assert_eq!(parse("systemd"), Ok(ListenAddr::Systemd));
+        for s in ["3000", ":3000", "localhost:http", "fd:x"] {
+            assert!(parse(s).is_err(), "{s}");
+        }
+        assert_eq!(
+            ListenAddr::Unix("/a.sock".into()).to_string(),
+            "unix:/a.sock"
+        );
+    }
+
+    #[tokio::test]
+    async fn listeners_should_serve_tcp_and_unix() {
+        let path = std::env::temp_dir().join(format!("dino-{}.sock", std::process::id()));
+        let addrs = [
+            ListenAddr::Tcp("127.0.0.1:0".into()),
+            ListenAddr::Unix(path.clone()),
+        ];
+        let listeners = Listener::bind_all(&addrs).await.unwrap();
+        let tcp = listeners[0].local_addr();
+        assert_eq!(
+            listeners[1].local_addr(),
+            format!("unix:{}", path.display())
+        );
+        let shutdown = ShutdownHandle::new();
+        for listener in listeners {
+            tokio::spawn(serve(listener, app(), None, shutdown.clone()));
+        }
+
+        let res = get_hello(TcpStream::connect(&tcp).await.unwrap()).await;
+        assert!(res.ends_with("hello"));
+        let res = get_hello(UnixStream::connect(&path).await.unwrap()).await;
+        assert!(res.ends_with("hello"));
+        shutdown.shutdown();
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn bind_unix_should_only_replace_stale_sockets() {
+        let dir = std::env::temp_dir();
+        let file = dir.join(format!("dino-{}.txt", std::process::id()));
+        fs::write(&file, "keep").unwrap();
+        assert!(bind_unix(&file).is_err());
+        assert_eq!(fs::read_to_string(&file).unwrap(), "keep");
+        fs::remove_file(file).unwrap();
+
+        let path = dir.join(format!("dino-stale-{}.sock", std::process::id()));
+        let live = bind_unix(&path).unwrap();
+        assert!(bind_unix(&path).is_err());
+        drop(live);
+        // the file stays once the listener is gone
+        assert!(path.exists());
+        assert!(bind_unix(&path).is_ok());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn listener_should_take_over_inherited_sockets() {
+        use std::os::fd::{AsRawFd, IntoRawFd};
+
+        let std_listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = std_listener.local_addr().unwrap();
+        let fd = std_listener.into_raw_fd();
+        let listener = Listener::from_fd(fd).unwrap();
+        assert!(matches!(listener, Listener::Tcp(_)));
+        assert!(Listener::from_fd(fd).is_err());
+
+        // anything else is refused and left open
+        let file = fs::File::open("Cargo.toml").unwrap();
+        assert!(Listener::from_fd(file.as_raw_fd()).is_err());
+        assert!(file.metadata().is_ok());
+        let socket = Socket::new(Domain::IPV4, Type::STREAM, None).unwrap();
+        assert!(Listener::from_fd(socket.as_raw_fd()).is_err());
+
+        let shutdown = ShutdownHandle::new();
+        tokio::spawn(serve(listener, app(), None, shutdown.clone()));
+        let res = get_hello(TcpStream::connect(addr).await.unwrap()).await;
+        assert!(res.ends_with("hello"));
+        shutdown.shutdown();
+    }
+}
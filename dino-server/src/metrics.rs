@@ -0,0 +1,263 @@
+use crate::TenantRouters;
+use axum::{
+    extract::State,
+    http::{header::CONTENT_TYPE, Method},
+    response::IntoResponse,
+    routing::get,
+    Router,
+};
+use prometheus::{
+    exponential_buckets, Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts,
+    Registry, TextEncoder,
+};
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex, OnceLock},
+};
+use tracing::warn;
+
+/// Content type of the prometheus text format.
+pub const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+/// Server wide metrics, shared by every tenant.
+pub(crate) struct Metrics {
+    registry: Registry,
+    pub requests: IntCounterVec,
+    pub latency: HistogramVec,
+    // js workers running a handler right now
+    pub busy_workers: IntGauge,
+    pub js_heap: HistogramVec,
+    pub timeouts: IntCounterVec,
+    pub exceptions: IntCounterVec,
+    pub swaps: IntCounterVec,
+}
+
+/// Tenant and route pattern of a request, filled in while it is routed so the
+/// metrics layer can label it once the response is known. Raw hosts and paths
+/// are never used as labels, they are unbounded.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RequestLabels(Arc<Mutex<Labels>>);
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Labels {
+    // tenant key, e.g. `*.example.com` or `example.com/billing`
+    pub tenant: String,
+    // route pattern, e.g. `/users/{id}`
+    pub route: String,
+}
+
+// decrements the busy worker gauge when dropped
+pub(crate) struct BusyWorker(IntGauge);
+
+impl Metrics {
+    fn try_new() -> prometheus::Result<Self> {
+        let registry = Registry::new_custom(Some("dino".to_string()), None)?;
+        let requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "Requests served"),
+            &["tenant", "route", "method", "status"],
+        )?;
+        let latency = HistogramVec::new(
+            HistogramOpts::new("http_request_duration_seconds", "Time to serve a request"),
+            &["tenant", "route", "method"],
+        )?;
+        let busy_workers = IntGauge::new("js_workers_busy", "Js workers running a handler")?;
+        // 64kb - 256mb
+        let js_heap = HistogramVec::new(
+            HistogramOpts::new(
+                "js_heap_bytes",
+                "Js heap used by a worker once its handler returns",
+            )
+            .buckets(exponential_buckets(65536.0, 4.0, 7)?),
+            &["tenant"],
+        )?;
+        let timeouts = IntCounterVec::new(
+            Opts::new(
+                "handler_timeouts_total",
+                "Handlers cut off by the route timeout",
+            ),
+            &["tenant", "route"],
+        )?;
+        let exceptions = IntCounterVec::new(
+            Opts::new(
+                "handler_exceptions_total",
+                "Handlers that threw or failed to run",
+            ),
+            &["tenant", "route"],
+        )?;
+        let swaps = IntCounterVec::new(
+            Opts::new(
+                "router_swaps_total",
+                "Versions swapped in, by where they came from",
+            ),
+            &["source"],
+        )?;
+        registry.register(Box::new(requests.clone()))?;
+        registry.register(Box::new(latency.clone()))?;
+        registry.register(Box::new(busy_workers.clone()))?;
+        registry.register(Box::new(js_heap.clone()))?;
+        registry.register(Box::new(timeouts.clone()))?;
+        registry.register(Box::new(exceptions.clone()))?;
+        registry.register(Box::new(swaps.clone()))?;
+
+        Ok(Self {
+            registry,
+            requests,
+            latency,
+            busy_workers,
+            js_heap,
+            timeouts,
+            exceptions,
+            swaps,
+        })
+    }
+
+    pub fn busy_worker(&self) -> BusyWorker {
+        self.busy_workers.inc();
+        BusyWorker(self.busy_workers.clone())
+    }
+}
+
+impl Drop for BusyWorker {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+pub(crate) fn metrics() -> &'static Metrics {
+    METRICS.get_or_init(|| Metrics::try_new().expect("metrics should be valid"))
+}
+
+impl RequestLabels {
+    pub fn set_tenant(&self, tenant: &str) {
+        self.0.lock().unwrap().tenant = tenant.to_string();
+    }
+
+    pub fn set_route(&self, route: &str) {
+        self.0.lock().unwrap().route = route.to_string();
+    }
+
+    pub fn get(&self) -> Labels {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+/// Label of a request method, extension methods are routable so they'd make
+/// unbounded series.
+pub(crate) fn method_label(method: &Method) -> &'static str {
+    match *method {
+        Method::GET => "GET",
+        Method::HEAD => "HEAD",
+        Method::POST => "POST",
+        Method::PUT => "PUT",
+        Method::DELETE => "DELETE",
+        Method::PATCH => "PATCH",
+        Method::OPTIONS => "OPTIONS",
+        Method::TRACE => "TRACE",
+        Method::CONNECT => "CONNECT",
+        _ => "other",
+    }
+}
+
+/// Serves `GET /metrics` without auth, for a listener of its own.
+pub fn metrics_router(routers: TenantRouters) -> Router {
+    Router::new()
+        .route("/metrics", get(get_metrics))
+        .with_state(routers)
+}
+
+pub(crate) async fn get_metrics(State(routers): State<TenantRouters>) -> impl IntoResponse {
+    (
+        [(CONTENT_TYPE, METRICS_CONTENT_TYPE)],
+        render_metrics(&routers),
+    )
+}
+
+/// Render every metric in the prometheus text format. Canary and shadow
+/// counters are read from the tenants at the time of the scrape.
+pub fn render_metrics(routers: &TenantRouters) -> String {
+    let mut families = metrics().registry.gather();
+    match tenant_families(routers) {
+        Ok(v) => families.extend(v),
+        Err(e) => warn!("failed to collect tenant metrics: {}", e),
+    }
+    let mut buf = vec![];
+    // only fails for invalid families, which are never built
+    let _ = TextEncoder::new().encode(&families, &mut buf);
+    String::from_utf8(buf).unwrap_or_default()
+}
+
+fn tenant_families(
+    routers: &TenantRouters,
+) -> prometheus::Result<Vec<prometheus::proto::MetricFamily>> {
+    let registry = Registry::new_custom(Some("dino".to_string()), None)?;
+    let canary = IntCounterVec::new(
+        Opts::new("canary_requests_total", "Requests split by the canary rule"),
+        &["tenant", "version"],
+    )?;
+    let shadow = IntCounterVec::new(
+        Opts::new("shadow_requests_total", "Requests mirrored to the shadow"),
+        &["tenant", "outcome"],
+    )?;
+    registry.register(Box::new(canary.clone()))?;
+    registry.register(Box::new(shadow.clone()))?;
+
+    // aliases share the router and its counters, they're reported once under
+    // the first key
+    let tenants = routers.entries();
+    let mut seen = HashSet::new();
+    for (tenant, router) in &tenants {
+        if !seen.insert(Arc::as_ptr(&router.inner)) {
+            continue;
+        }
+        let tenant = tenant.as_str();
+        if let Some(v) = router.canary() {
+            let stable = canary.with_label_values(&[tenant, "stable"]);
+            stable.inc_by(v.stable_requests);
+            let canary = canary.with_label_values(&[tenant, "canary"]);
+            canary.inc_by(v.canary_requests);
+        }
+        if let Some(v) = router.shadow() {
+            for (outcome, n) in [
+                ("mirrored", v.mirrored),
+                ("status_diff", v.status_diffs),
+                ("body_diff", v.body_diffs),
+                ("error", v.errors),
+            ] {
+                shadow.with_label_values(&[tenant, outcome]).inc_by(n);
+            }
+        }
+    }
+    Ok(registry.gather())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_metrics_should_use_prometheus_text_format() {
+        let m = metrics();
+        m.swaps.with_label_values(&["test"]).inc();
+        m.requests
+            .with_label_values(&["example.com", "/users/{id}", "GET", "200"])
+            .inc();
+        drop(m.busy_worker());
+
+        let text = render_metrics(&Default::default());
+        assert!(text.contains("# TYPE dino_http_requests_total counter"));
+        assert!(text.contains(
+            r#"dino_http_requests_total{method="GET",route="/users/{id}",status="200",tenant="example.com"}"#
+        ));
+        assert!(text.contains(r#"dino_router_swaps_total{source="test"} 1"#));
+        assert!(text.contains("dino_js_workers_busy"));
+    }
+
+    #[test]
+    fn method_label_should_bound_extension_methods() {
+        assert_eq!(method_label(&Method::PATCH), "PATCH");
+        let custom = Method::from_bytes(b"PROPFIND").unwrap();
+        assert_eq!(method_label(&custom), "other");
+    }
+}
@@ -0,0 +1,50 @@
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{
+        header::{AUTHORIZATION, WWW_AUTHENTICATE},
+        HeaderValue, StatusCode,
+    },
+    response::{IntoResponse, Response},
+};
+use tower_http::validate_request::ValidateRequestHeaderLayer;
+
+/// Credentials of an `Authorization` header value, if it uses `scheme`.
+pub(crate) fn credentials<'a>(value: &'a str, scheme: &str) -> Option<&'a str> {
+    value
+        .split_once(' ')
+        .filter(|(s, _)| s.eq_ignore_ascii_case(scheme))
+        .map(|(_, v)| v.trim())
+}
+
+/// Reject requests with a 401 and a `WWW-Authenticate: {scheme}` challenge,
+/// unless their `Authorization` header uses `scheme` and `check` accepts
+/// the credentials.
+// the validator signature is fixed by tower-http
+#[allow(clippy::result_large_err)]
+pub(crate) fn auth_layer<F>(
+    scheme: &str,
+    check: F,
+) -> ValidateRequestHeaderLayer<impl FnMut(&mut Request) -> Result<(), Response> + Clone>
+where
+    F: Fn(&str) -> bool + Clone,
+{
+    let scheme = scheme.to_string();
+    ValidateRequestHeaderLayer::custom(move |req: &mut Request| {
+        let authorized = req
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| credentials(v, &scheme))
+            .is_some_and(&check);
+        if authorized {
+            return Ok(());
+        }
+
+        let mut res = (StatusCode::UNAUTHORIZED, Body::empty()).into_response();
+        if let Ok(v) = HeaderValue::from_str(&scheme) {
+            res.headers_mut().insert(WWW_AUTHENTICATE, v);
+        }
+        Err(res)
+    })
+}
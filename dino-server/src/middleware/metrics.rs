@@ -0,0 +1,64 @@
+use crate::metrics::{method_label, metrics, RequestLabels};
+use axum::{extract::Request, response::Response};
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::time::Instant;
+use tower::{Layer, Service};
+
+/// Count requests and record their latency by tenant, route, method and status.
+#[derive(Clone)]
+pub struct MetricsLayer;
+
+impl<S> Layer<S> for MetricsLayer {
+    type Service = MetricsMiddleware<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        MetricsMiddleware { inner }
+    }
+}
+
+#[derive(Clone)]
+pub struct MetricsMiddleware<S> {
+    inner: S,
+}
+
+impl<S> Service<Request> for MetricsMiddleware<S>
+where
+    S: Service<Request, Response = Response> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request) -> Self::Future {
+        let start = Instant::now();
+        let method = method_label(request.method());
+        // filled in by the handler once the tenant and route are known
+        let labels = RequestLabels::default();
+        request.extensions_mut().insert(labels.clone());
+        let future = self.inner.call(request);
+        Box::pin(async move {
+            let res: Response = future.await?;
+            let labels = labels.get();
+            let m = metrics();
+            let (tenant, route) = (labels.tenant.as_str(), labels.route.as_str());
+            m.requests
+                .with_label_values(&[tenant, route, method, res.status().as_str()])
+                .inc();
+            m.latency
+                .with_label_values(&[tenant, route, method])
+                .observe(start.elapsed().as_secs_f64());
+
+            Ok(res)
+        })
+    }
+}
@@ -1,6 +1,15 @@
+mod auth;
+mod metrics;
+mod request_id;
+mod route;
 mod server_time;
 
 const SERVER_TIME_HEADER: &str = "x-server-time";
-const REQUEST_ID_HEADER: &str = "x-request-id";
+pub(crate) const REQUEST_ID_HEADER: &str = "x-request-id";
 
+pub(crate) use auth::{auth_layer, credentials};
+pub use metrics::MetricsLayer;
+pub(crate) use request_id::request_id;
+pub use request_id::RequestIdLayer;
+pub(crate) use route::call_route;
 pub use server_time::ServerTimeLayer;
@@ -0,0 +1,121 @@
+use super::REQUEST_ID_HEADER;
+use axum::{extract::Request, http::HeaderValue, response::Response};
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tower::{Layer, Service};
+use tracing::{info_span, Instrument};
+use uuid::Uuid;
+
+// longer ids are replaced, they'd only bloat logs
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Give every request an id: the `x-request-id` it came with, or a new UUIDv7.
+/// The id is set on the request, echoed in the response and recorded on the
+/// tracing span of the request.
+#[derive(Clone)]
+pub struct RequestIdLayer;
+
+impl<S> Layer<S> for RequestIdLayer {
+    type Service = RequestIdMiddleware<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequestIdMiddleware { inner }
+    }
+}
+
+#[derive(Clone)]
+pub struct RequestIdMiddleware<S> {
+    inner: S,
+}
+
+/// The incoming id if it's usable, a new UUIDv7 otherwise.
+pub(crate) fn request_id(incoming: Option<&str>) -> String {
+    match incoming {
+        Some(v) if is_valid(v) => v.to_string(),
+        _ => Uuid::now_v7().to_string(),
+    }
+}
+
+fn is_valid(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN && id.bytes().all(|b| b.is_ascii_graphic())
+}
+
+impl<S> Service<Request> for RequestIdMiddleware<S>
+where
+    S: Service<Request, Response = Response> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request) -> Self::Future {
+        let incoming = request.headers().get(REQUEST_ID_HEADER);
+        let id = request_id(incoming.and_then(|v| v.to_str().ok()));
+        // only visible ascii is kept, so it's always a valid header value
+        let value = HeaderValue::from_str(&id).expect("request id should be a valid header");
+        request
+            .headers_mut()
+            .insert(REQUEST_ID_HEADER, value.clone());
+        let span = info_span!("request", request_id = %id);
+        let future = self.inner.call(request).instrument(span);
+        Box::pin(async move {
+            let mut res: Response = future.await?;
+            res.headers_mut().insert(REQUEST_ID_HEADER, value);
+            Ok(res)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use std::convert::Infallible;
+    use tower::{service_fn, ServiceExt};
+
+    async fn call(id: Option<&str>) -> (String, String) {
+        let service = RequestIdLayer.layer(service_fn(|req: Request| async move {
+            let seen = req.headers()[REQUEST_ID_HEADER]
+                .to_str()
+                .unwrap()
+                .to_string();
+            Ok::<_, Infallible>(Response::new(Body::from(seen)))
+        }));
+        let mut req = Request::builder().uri("/");
+        if let Some(id) = id {
+            req = req.header(REQUEST_ID_HEADER, id);
+        }
+        let res = service
+            .oneshot(req.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let echoed = res.headers()[REQUEST_ID_HEADER]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let body = axum::body::to_bytes(res.into_body(), 1024).await.unwrap();
+        (String::from_utf8(body.to_vec()).unwrap(), echoed)
+    }
+
+    #[tokio::test]
+    async fn request_id_layer_should_keep_or_generate_id() {
+        let (seen, echoed) = call(Some("abc-123")).await;
+        assert_eq!((seen.as_str(), echoed.as_str()), ("abc-123", "abc-123"));
+
+        for id in [None, Some(""), Some("has space"), Some(&*"x".repeat(129))] {
+            let (seen, echoed) = call(id).await;
+            assert_eq!(seen, echoed);
+            let uuid = Uuid::parse_str(&seen).unwrap();
+            assert_eq!(uuid.get_version_num(), 7);
+        }
+    }
+}
@@ -0,0 +1,217 @@
+use super::auth_layer;
+use crate::{AppError, CorsConfig, RouteOptions};
+use anyhow::anyhow;
+use axum::{
+    extract::Request,
+    http::{header::CACHE_CONTROL, HeaderName, HeaderValue, Method},
+    response::Response,
+};
+use std::{convert::Infallible, time::Duration};
+use tower::{timeout::error::Elapsed, Service, ServiceBuilder, ServiceExt};
+use tower_http::cors::{AllowHeaders, AllowMethods, AllowOrigin, Any, CorsLayer};
+
+/// Run the request through the layers selected by the route options.
+pub(crate) async fn call_route<S>(
+    options: &RouteOptions,
+    service: S,
+    req: Request,
+) -> Result<Response, AppError>
+where
+    S: Service<Request, Response = Response, Error = Infallible> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    let service = ServiceBuilder::new()
+        .option_layer(options.cors.as_ref().map(cors_layer))
+        .option_layer(options.auth.as_deref().map(|scheme| {
+            // only checks that a credential is present, it doesn't validate
+            // it: that's up to the handler
+            auth_layer(scheme, |v| !v.is_empty())
+        }))
+        .option_layer(options.timeout.map(tower::timeout::TimeoutLayer::new))
+        .service(service);
+
+    let cacheable = matches!(*req.method(), Method::GET | Method::HEAD);
+    let mut res = service.oneshot(req).await.map_err(|e| {
+        if e.is::<Elapsed>() {
+            AppError::HandlerTimeout
+        } else {
+            anyhow!(e).into()
+        }
+    })?;
+    if let Some(max_age) = options.cache.filter(|_| cacheable) {
+        set_cache_control(&mut res, max_age, options.auth.is_some());
+    }
+    Ok(res)
+}
+
+// only successful responses are cached, and never by shared caches if the
+// response depends on the credentials
+fn set_cache_control(res: &mut Response, max_age: Duration, private: bool) {
+    if !res.status().is_success() {
+        return;
+    }
+    let scope = if private { "private" } else { "public" };
+    let value = format!("{}, max-age={}", scope, max_age.as_secs());
+    if let Ok(v) = HeaderValue::from_str(&value) {
+        res.headers_mut().entry(CACHE_CONTROL).or_insert(v);
+    }
+}
+
+pub(crate) fn cors_layer(config: &CorsConfig) -> CorsLayer {
+    let any_origin = config.origins.iter().any(|v| v == "*");
+    // credentials can't be combined with wildcards, mirror the request instead
+    let origin = match (any_origin, config.credentials) {
+        (true, true) => AllowOrigin::mirror_request(),
+        (true, false) => AllowOrigin::from(Any),
+        _ => AllowOrigin::list(
+            config
+                .origins
+                .iter()
+                .filter_map(|v| HeaderValue::from_str(v).ok()),
+        ),
+    };
+    let any_method = config.methods.iter().any(|v| v == "*");
+    let methods = match (config.methods.is_empty(), any_method, config.credentials) {
+        (true, ..) | (_, true, true) => AllowMethods::mirror_request(),
+        (_, true, false) => AllowMethods::from(Any),
+        _ => AllowMethods::list(
+            config
+                .methods
+                .iter()
+                .filter_map(|v| Method::from_bytes(v.to_uppercase().as_bytes()).ok()),
+        ),
+    };
+    let any_header = config.headers.iter().any(|v| v == "*");
+    let headers = match (config.headers.is_empty(), any_header, config.credentials) {
+        (true, ..) | (_, true, true) => AllowHeaders::mirror_request(),
+        (_, true, false) => AllowHeaders::from(Any),
+        _ => AllowHeaders::list(
+            config
+                .headers
+                .iter()
+                .filter_map(|v| HeaderName::from_bytes(v.as_bytes()).ok()),
+        ),
+    };
+
+    let layer = CorsLayer::new()
+        .allow_origin(origin)
+        .allow_methods(methods)
+        .allow_headers(headers)
+        .allow_credentials(config.credentials);
+    match config.max_age {
+        Some(v) => layer.max_age(v),
+        None => layer,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        body::Body,
+        http::{
+            header::{AUTHORIZATION, WWW_AUTHENTICATE},
+            StatusCode,
+        },
+        response::IntoResponse,
+    };
+    use tower::service_fn;
+
+    async fn call(options: &RouteOptions, req: Request) -> Result<Response, AppError> {
+        let service = service_fn(|_req: Request| async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            Ok::<_, Infallible>("ok".into_response())
+        });
+        call_route(options, service, req).await
+    }
+
+    #[tokio::test]
+    async fn route_layers_should_enforce_auth_and_cache() {
+        let options = RouteOptions {
+            auth: Some("bearer".to_string()),
+            cache: Some(Duration::from_secs(60)),
+            ..Default::default()
+        };
+        let req = Request::builder().uri("/").body(Body::empty()).unwrap();
+        let res = call(&options, req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(res.headers()[WWW_AUTHENTICATE], "bearer");
+        assert!(res.headers().get(CACHE_CONTROL).is_none());
+
+        let req = Request::builder()
+            .uri("/")
+            .header(AUTHORIZATION, "Bearer abc")
+            .body(Body::empty())
+            .unwrap();
+        let res = call(&options, req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[CACHE_CONTROL], "private, max-age=60");
+
+        let options = RouteOptions {
+            cache: Some(Duration::from_secs(60)),
+            ..Default::default()
+        };
+        let req = Request::builder().uri("/").body(Body::empty()).unwrap();
+        let res = call(&options, req).await.unwrap();
+        assert_eq!(res.headers()[CACHE_CONTROL], "public, max-age=60");
+        let req = Request::builder()
+            .method(Method::POST)
+            .uri("/")
+            .body(Body::empty())
+            .unwrap();
+        let res = call(&options, req).await.unwrap();
+        assert!(res.headers().get(CACHE_CONTROL).is_none());
+    }
+
+    #[tokio::test]
+    async fn route_layers_should_enforce_timeout_and_cors() {
+        let options = RouteOptions {
+            timeout: Some(Duration::from_millis(10)),
+            ..Default::default()
+        };
+        let req = Request::builder().uri("/").body(Body::empty()).unwrap();
+        let ret = call(&options, req).await;
+        assert!(matches!(ret, Err(AppError::HandlerTimeout)));
+
+        let options = RouteOptions {
+            cors: Some(CorsConfig {
+                origins: vec!["https://example.com".to_string()],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let req = Request::builder()
+            .uri("/")
+            .header("origin", "https://example.com")
+            .body(Body::empty())
+            .unwrap();
+        let res = call(&options, req).await.unwrap();
+        assert_eq!(
+            res.headers()["access-control-allow-origin"],
+            "https://example.com"
+        );
+
+        // wildcards with credentials mirror the preflight request
+        let options = RouteOptions {
+            cors: Some(CorsConfig {
+                origins: vec!["*".to_string()],
+                methods: vec!["*".to_string()],
+                headers: vec!["*".to_string()],
+                credentials: true,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let req = Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/")
+            .header("origin", "https://example.com")
+            .header("access-control-request-method", "PUT")
+            .header("access-control-request-headers", "x-token")
+            .body(Body::empty())
+            .unwrap();
+        let res = call(&options, req).await.unwrap();
+        assert_eq!(res.headers()["access-control-allow-methods"], "PUT");
+        assert_eq!(res.headers()["access-control-allow-headers"], "x-token");
+    }
+}
@@ -0,0 +1,278 @@
+use crate::{load_schema, ProjectConfig, ProjectRoute, RouteAction, RouteMethod};
+use anyhow::{anyhow, Result};
+use axum::http::Method;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+const OPENAPI_VERSION: &str = "3.0.3";
+// methods documented for routes that accept any method
+const ANY_METHODS: [Method; 5] = [
+    Method::GET,
+    Method::POST,
+    Method::PUT,
+    Method::PATCH,
+    Method::DELETE,
+];
+
+/// Serve the OpenAPI document of the project.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct OpenApiConfig {
+    #[serde(default = "default_path")]
+    pub path: String,
+    // the project name if not set
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default = "default_version")]
+    pub version: String,
+}
+
+/// The document and the path it's served at.
+#[derive(Debug, Clone)]
+pub struct OpenApi {
+    pub path: String,
+    pub document: Value,
+}
+
+fn default_path() -> String {
+    "/openapi.json".to_string()
+}
+
+fn default_version() -> String {
+    "1.0.0".to_string()
+}
+
+impl Default for OpenApiConfig {
+    fn default() -> Self {
+        Self {
+            path: default_path(),
+            title: None,
+            version: default_version(),
+        }
+    }
+}
+
+/// Describe every route of the project as an OpenAPI 3 document. Schema
+/// files that can't be loaded fail it, just like they fail the routes.
+pub fn openapi_document(config: &ProjectConfig) -> Result<Value> {
+    let options = config.openapi.clone().unwrap_or_default();
+    let mut paths = Map::new();
+    for (path, routes) in &config.routes {
+        let (path, params) = convert_path(path);
+        let mut item = Map::new();
+        for route in routes {
+            let op = operation(route, &params).map_err(|e| anyhow!("route {}: {:#}", path, e))?;
+            for method in methods(&route.methods) {
+                // the first route of a method wins, just like when matching
+                item.entry(method.as_str().to_lowercase())
+                    .or_insert_with(|| op.clone());
+            }
+        }
+        paths.insert(path, Value::Object(item));
+    }
+
+    Ok(json!({
+        "openapi": OPENAPI_VERSION,
+        "info": {
+            "title": options.title.unwrap_or_else(|| config.name.clone()),
+            "version": options.version,
+        },
+        "paths": paths,
+    }))
+}
+
+// `/users/:id/*rest` => `/users/{id}/{rest}` and its param names
+fn convert_path(path: &str) -> (String, Vec<String>) {
+    let mut params = vec![];
+    let segments: Vec<String> = path
+        .split('/')
+        .map(|s| match s.strip_prefix([':', '*']) {
+            Some(name) => {
+                params.push(name.to_string());
+                format!("{{{name}}}")
+            }
+            None => s.to_string(),
+        })
+        .collect();
+    (segments.join("/"), params)
+}
+
+// extension methods like PROPFIND can't be described by OpenAPI
+fn methods(methods: &[RouteMethod]) -> Vec<Method> {
+    let mut ret = vec![];
+    for m in methods {
+        match m {
+            RouteMethod::Any => ret.extend(ANY_METHODS),
+            RouteMethod::Method(m) if is_standard(m) => ret.push(m.clone()),
+            RouteMethod::Method(_) => {}
+        }
+    }
+    ret
+}
+
+fn is_standard(m: &Method) -> bool {
+    [
+        Method::GET,
+        Method::POST,
+        Method::PUT,
+        Method::PATCH,
+        Method::DELETE,
+        Method::HEAD,
+        Method::OPTIONS,
+        Method::TRACE,
+    ]
+    .contains(m)
+}
+
+fn operation(route: &ProjectRoute, params: &[String]) -> Result<Value> {
+    let mut op = Map::new();
+    if let Some(summary) = &route.summary {
+        op.insert("summary".into(), summary.clone().into());
+    }
+    // inline param / query schemas describe each parameter
+    let params_schema = route.params_schema.as_ref().map(load_schema).transpose()?;
+    let path_props = &params_schema.as_ref().unwrap_or(&Value::Null)["properties"];
+    let mut parameters: Vec<_> = params
+        .iter()
+        .map(|name| {
+            let schema = match &path_props[name] {
+                Value::Null => json!({ "type": "string" }),
+                v => v.clone(),
+            };
+            json!({ "name": name, "in": "path", "required": true, "schema": schema })
+        })
+        .collect();
+    if let Some(query) = route.query_schema.as_ref().map(load_schema).transpose()? {
+        let required = query["required"].as_array();
+        let props = query["properties"].as_object().into_iter().flatten();
+        parameters.extend(props.map(|(name, schema)| {
+            let required = required.is_some_and(|v| v.iter().any(|v| v == name));
+            json!({ "name": name, "in": "query", "required": required, "schema": schema })
+        }));
+    }
+    if !parameters.is_empty() {
+        op.insert("parameters".into(), parameters.into());
+    }
+    if let Some(schema) = &route.request_schema {
+        let body = json!({
+            "required": true,
+            "content": { "application/json": { "schema": load_schema(schema)? } },
+        });
+        op.insert("requestBody".into(), body);
+    }
+
+    let mut responses = Map::new();
+    let (status, description) = match route.action() {
+        Ok(RouteAction::Redirect(v)) => (v.status, format!("Redirect to {}", v.to)),
+        Ok(RouteAction::Respond(v)) => (v.status, "Fixed response".to_string()),
+        _ => (200, "OK".to_string()),
+    };
+    let mut res = json!({ "description": description });
+    if let Some(schema) = &route.response_schema {
+        res["content"] = json!({ "application/json": { "schema": load_schema(schema)? } });
+    }
+    responses.insert(status.to_string(), res);
+    let schemas = [
+        &route.request_schema,
+        &route.query_schema,
+        &route.params_schema,
+    ];
+    if schemas.iter().any(|v| v.is_some()) {
+        responses.insert("400".into(), json!({ "description": "Invalid request" }));
+    }
+    if route.options.auth.is_some() {
+        responses.insert("401".into(), json!({ "description": "Unauthorized" }));
+    }
+    op.insert("responses".into(), responses.into());
+    Ok(Value::Object(op))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn openapi_document_should_describe_routes() {
+        let config = r#"
+name: users
+openapi:
+  version: 2.0.0
+routes:
+  /api/users/:id:
+    - method: GET
+      handler: getUser
+      summary: Get a user
+      auth: bearer
+      response_schema:
+        type: object
+        properties:
+          name: { type: string }
+    - method: [PUT, PROPFIND]
+      handler: updateUser
+      request_schema:
+        type: object
+      params_schema:
+        properties:
+          id: { type: string, pattern: "^[0-9]+$" }
+      query_schema:
+        required: [force]
+        properties:
+          force: { type: string }
+  /old/*rest:
+    - method: ANY
+      redirect:
+        to: /new/*rest
+        status: 301
+"#;
+        let config: ProjectConfig = serde_yaml::from_str(config).unwrap();
+        let doc = openapi_document(&config).unwrap();
+        assert_eq!(doc["info"], json!({ "title": "users", "version": "2.0.0" }));
+
+        let user = &doc["paths"]["/api/users/{id}"];
+        assert_eq!(user["get"]["summary"], "Get a user");
+        assert_eq!(user["get"]["parameters"][0]["name"], "id");
+        assert_eq!(
+            user["get"]["responses"]["200"]["content"]["application/json"]["schema"]["type"],
+            "object"
+        );
+        assert!(user["get"]["responses"]["401"].is_object());
+        assert!(user["put"]["requestBody"].is_object());
+        assert!(user["put"]["responses"]["400"].is_object());
+        let params = &user["put"]["parameters"];
+        assert_eq!(params[0]["schema"]["pattern"], "^[0-9]+$");
+        assert_eq!(params[1]["name"], "force");
+        assert_eq!(params[1]["required"], true);
+        assert!(user.get("propfind").is_none());
+
+        let old = doc["paths"]["/old/{rest}"].as_object().unwrap();
+        assert_eq!(old.len(), ANY_METHODS.len());
+        assert_eq!(
+            old["get"]["responses"]["301"]["description"],
+            "Redirect to /new/*rest"
+        );
+    }
+
+    #[test]
+    fn openapi_document_should_inline_schema_files() {
+        let config = r#"
+name: users
+routes:
+  /users:
+    - method: POST
+      handler: createUser
+      request_schema: fixtures/user.schema.json
+"#;
+        let yaml = config;
+        let config: ProjectConfig = serde_yaml::from_str(yaml).unwrap();
+        let doc = openapi_document(&config).unwrap();
+        let body = &doc["paths"]["/users"]["post"]["requestBody"];
+        let schema = &body["content"]["application/json"]["schema"];
+        assert_eq!(schema["type"], "object");
+        assert!(schema.get("$ref").is_none());
+
+        let config = yaml.replace("fixtures/user.schema.json", "fixtures/missing.json");
+        let config: ProjectConfig = serde_yaml::from_str(&config).unwrap();
+        assert!(openapi_document(&config).is_err());
+    }
+}
@@ -0,0 +1,150 @@
+(function () {
+  const parseForm = __dino_parse_form;
+  const decodeUtf8 = __dino_decode_utf8;
+
+  class File {
+    constructor(entry) {
+      this.name = entry.filename;
+      this.type = entry.content_type || "application/octet-stream";
+      this.size = entry.data.byteLength;
+      Object.defineProperty(this, "data", { value: entry.data });
+    }
+
+    async arrayBuffer() {
+      return this.data;
+    }
+
+    async text() {
+      return decodeUtf8(this.data);
+    }
+  }
+
+  class FormData {
+    constructor(entries) {
+      Object.defineProperty(this, "entries_", { value: entries });
+    }
+
+    get(name) {
+      const entry = this.entries_.find(([k]) => k === name);
+      return entry ? entry[1] : null;
+    }
+
+    getAll(name) {
+      return this.entries_.filter(([k]) => k === name).map(([, v]) => v);
+    }
+
+    has(name) {
+      return this.entries_.some(([k]) => k === name);
+    }
+
+    keys() {
+      return this.entries_.map(([k]) => k)[Symbol.iterator]();
+    }
+
+    values() {
+      return this.entries_.map(([, v]) => v)[Symbol.iterator]();
+    }
+
+    entries() {
+      return this.entries_[Symbol.iterator]();
+    }
+
+    [Symbol.iterator]() {
+      return this.entries();
+    }
+  }
+
+  function toFormData(entries) {
+    return new FormData(
+      entries.map((e) => [e.name, e.filename == null ? e.value : new File(e)])
+    );
+  }
+
+  function toServiceRequest(input, init = {}) {
+    const req = typeof input === "string" ? { url: input } : { ...input };
+    const body = init.body ?? req.body;
+    return {
+      method: (init.method ?? req.method ?? "GET").toUpperCase(),
+      url: req.url,
+      headers: { ...(req.headers ?? {}), ...(init.headers ?? {}) },
+      body: body == null || typeof body === "string" ? body : JSON.stringify(body),
+    };
+  }
+
+  function toServiceResponse(res) {
+    return {
+      ...res,
+      ok: res.status >= 200 && res.status < 300,
+      text: async () => res.body ?? "",
+      json: async () => JSON.parse(res.body ?? ""),
+    };
+  }
+
+  // build the `env` passed to handlers from service bindings (name => host)
+  function bind(hosts, fetch) {
+    const env = {};
+    for (const [name, host] of Object.entries(hosts)) {
+      env[name] = {
+        fetch: async (input, init) =>
+          toServiceResponse(fetch(host, toServiceRequest(input, init))),
+      };
+    }
+    return env;
+  }
+
+  // wrap the request passed to handlers with lazily parsed body accessors
+  function request(req) {
+    const raw = req.raw_body;
+    delete req.raw_body;
+    req.queryAll = req.query_all;
+    req.rawQuery = req.raw_query;
+    req.requestId = req.request_id;
+    delete req.query_all;
+    delete req.raw_query;
+    delete req.request_id;
+
+    let json, formData;
+    Object.defineProperties(req, {
+      text: { value: async () => req.body ?? "" },
+      arrayBuffer: { value: async () => raw ?? new ArrayBuffer(0) },
+      json: {
+        value: async () => {
+          if (json === undefined) json = JSON.parse(req.body ?? "");
+          return json;
+        },
+      },
+      formData: {
+        value: async () => {
+          if (formData === undefined) {
+            formData = toFormData(parseForm(req.headers["content-type"] ?? "", raw));
+          }
+          return formData;
+        },
+      },
+    });
+    return req;
+  }
+
+  // promises passed to `ctx.waitUntil()`, settled after the response is sent
+  const pending = [];
+
+  // the `ctx` passed to handlers
+  function context() {
+    return {
+      waitUntil: (promise) => {
+        pending.push(Promise.resolve(promise));
+      },
+    };
+  }
+
+  function settle() {
+    return Promise.allSettled(pending.splice(0));
+  }
+
+  // call a function that may or may not be async
+  async function call(fun, ...args) {
+    return fun(...args);
+  }
+
+  return { request, bind, call, context, settle };
+})();
@@ -0,0 +1,194 @@
+use serde_yaml::{Mapping, Value};
+
+const PROFILES_KEY: &str = "profiles";
+
+/// Merge the selected profile over the base config and drop `profiles:`.
+pub fn apply_profile(value: &mut Value, profile: Option<&str>) -> Result<(), String> {
+    let profiles = match value.as_mapping_mut() {
+        Some(v) => v.remove(PROFILES_KEY),
+        None => None,
+    };
+    let Some(name) = profile else {
+        return Ok(());
+    };
+
+    let mut profiles = match profiles {
+        Some(Value::Mapping(v)) => v,
+        _ => Mapping::new(),
+    };
+    let Some(overlay) = profiles.remove(name) else {
+        let names = profiles
+            .keys()
+            .filter_map(|k| k.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+        return Err(format!("unknown profile `{name}`, available: {names}"));
+    };
+    merge(value, overlay);
+    Ok(())
+}
+
+// mappings are merged key by key, anything else is replaced
+fn merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Mapping(base), Value::Mapping(overlay)) => {
+            for (k, v) in overlay {
+                match base.get_mut(&k) {
+                    Some(b) => merge(b, v),
+                    None => {
+                        base.insert(k, v);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// Replace `${VAR}` / `${VAR:-default}` in every string value.
+pub fn interpolate<F>(value: &mut Value, vars: &F) -> Result<(), String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    match value {
+        // the result is always a string, it's never parsed as yaml again
+        Value::String(s) if s.contains('$') => *s = interpolate_str(s, vars)?,
+        Value::Mapping(m) => {
+            for (_, v) in m.iter_mut() {
+                interpolate(v, vars)?;
+            }
+        }
+        Value::Sequence(items) => {
+            for v in items {
+                interpolate(v, vars)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Interpolate a single string, `$$` escapes a literal `$`.
+pub fn interpolate_str<F>(s: &str, vars: &F) -> Result<String, String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut ret = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(idx) = rest.find('$') {
+        ret.push_str(&rest[..idx]);
+        rest = &rest[idx..];
+        if let Some(v) = rest.strip_prefix("$$") {
+            ret.push('$');
+            rest = v;
+            continue;
+        }
+        let Some(v) = rest.strip_prefix("${") else {
+            ret.push('$');
+            rest = &rest[1..];
+            continue;
+        };
+        let end = v
+            .find('}')
+            .ok_or_else(|| format!("unterminated variable in `{s}`"))?;
+        let (name, default) = match v[..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&v[..end], None),
+        };
+        if !is_var_name(name) {
+            return Err(format!("invalid variable name `{name}`"));
+        }
+        let value = vars(name)
+            .or_else(|| default.map(|v| v.to_string()))
+            .ok_or_else(|| format!("undefined variable `{name}`"))?;
+        ret.push_str(&value);
+        rest = &v[end + 1..];
+    }
+    ret.push_str(rest);
+    Ok(ret)
+}
+
+/// Variables come from the process environment.
+pub fn env_var(name: &str) -> Option<String> {
+    std::env::var(name).ok()
+}
+
+fn is_var_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(name: &str) -> Option<String> {
+        match name {
+            "HOST" => Some("example.com".to_string()),
+            "PORT" => Some("8080".to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn interpolate_str_should_work() {
+        assert_eq!(
+            interpolate_str("https://${HOST}:${PORT}/", &vars).unwrap(),
+            "https://example.com:8080/"
+        );
+        assert_eq!(interpolate_str("${NAME:-dino}", &vars).unwrap(), "dino");
+        assert_eq!(interpolate_str("$$HOME $5", &vars).unwrap(), "$HOME $5");
+        assert_eq!(
+            interpolate_str("${NAME}", &vars).unwrap_err(),
+            "undefined variable `NAME`"
+        );
+        assert!(interpolate_str("${HOST", &vars).is_err());
+    }
+
+    #[test]
+    fn interpolate_should_substitute_strings() {
+        let vars = |name: &str| match name {
+            "HANDLER" => Some("123".to_string()),
+            "TO" => Some("https://a.com/#top".to_string()),
+            "MAP" => Some("a: b".to_string()),
+            _ => None,
+        };
+        let mut value: Value =
+            serde_yaml::from_str("handler: ${HANDLER}\nto: ${TO}\nheader: ${MAP}").unwrap();
+        interpolate(&mut value, &vars).unwrap();
+        assert_eq!(value["handler"], "123");
+        assert_eq!(value["to"], "https://a.com/#top");
+        assert_eq!(value["header"], "a: b");
+    }
+
+    #[test]
+    fn apply_profile_should_merge_overlay() {
+        let content = r#"
+name: test
+limits:
+  body: 1mb
+  headers: 10
+timeout: ${TIMEOUT:-5}
+profiles:
+  prod:
+    name: test-prod
+    limits:
+      body: 10mb
+"#;
+        let mut value: Value = serde_yaml::from_str(content).unwrap();
+        apply_profile(&mut value, Some("prod")).unwrap();
+        interpolate(&mut value, &vars).unwrap();
+        assert_eq!(value["name"], "test-prod");
+        assert_eq!(value["limits"]["body"], "10mb");
+        assert_eq!(value["limits"]["headers"], 10);
+        assert_eq!(value["timeout"], "5");
+        assert!(value.get(PROFILES_KEY).is_none());
+
+        let mut value: Value = serde_yaml::from_str(content).unwrap();
+        let err = apply_profile(&mut value, Some("dev")).unwrap_err();
+        assert_eq!(err, "unknown profile `dev`, available: prod");
+    }
+}
@@ -0,0 +1,464 @@
+use crate::{
+    actions::validate_headers,
+    config::{deserialize_from_str, deserialize_opt_duration, duration_schema},
+    listen::ConnInfo,
+    substitute, AppError, AppRouter, JsWorker, Req, ServiceContext, ServiceReq, FORWARDED_PROTO,
+};
+use anyhow::anyhow;
+use axum::{
+    body::{Body, Bytes},
+    extract::Request,
+    http::{
+        header::{CONNECTION, CONTENT_LENGTH, HOST, TE, TRAILER, TRANSFER_ENCODING, UPGRADE},
+        HeaderMap, HeaderName, HeaderValue, Method,
+    },
+    response::Response,
+};
+use indexmap::IndexMap;
+use percent_encoding::percent_decode_str;
+use reqwest::Client;
+use schemars::{
+    gen::SchemaGenerator,
+    schema::{Schema, SchemaObject, SubschemaValidation},
+    JsonSchema,
+};
+use serde::{Deserialize, Deserializer};
+use std::{collections::HashMap, sync::OnceLock, time::Duration};
+use tokio::task::spawn_blocking;
+use tracing::warn;
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+const X_FORWARDED_HOST: &str = "x-forwarded-host";
+// set from the connection, whatever the client sent is dropped
+const FORWARDED: [&str; 4] = [
+    "forwarded",
+    X_FORWARDED_FOR,
+    X_FORWARDED_HOST,
+    FORWARDED_PROTO,
+];
+
+// headers that only make sense for a single connection
+const HOP_BY_HOP: [HeaderName; 5] = [CONNECTION, TE, TRAILER, TRANSFER_ENCODING, UPGRADE];
+
+/// Forward matching requests to an upstream http service.
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct ProxyConfig {
+    // upstream url, `:name` / `*name` are replaced by path params
+    pub to: String,
+    // set on the upstream request, values may use path params too
+    #[serde(default)]
+    pub headers: IndexMap<String, String>,
+    // removed from the upstream request
+    #[serde(default)]
+    pub remove_headers: Vec<String>,
+    // set on the response sent back to the client
+    #[serde(default)]
+    pub response_headers: IndexMap<String, String>,
+    // timeout of every attempt
+    #[serde(default, deserialize_with = "deserialize_opt_duration")]
+    #[schemars(schema_with = "duration_schema")]
+    pub timeout: Option<Duration>,
+    // extra attempts on connection errors, for idempotent methods only
+    #[serde(default, deserialize_with = "deserialize_from_str")]
+    pub retries: u32,
+    // js function that gets the upstream request and returns it, possibly changed
+    #[serde(default)]
+    pub hook: Option<String>,
+}
+
+// the upstream request, the hook may change any part of it
+struct Upstream {
+    method: Method,
+    url: String,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+fn client() -> &'static Client {
+    static CLIENT: OnceLock<Client> = OnceLock::new();
+    CLIENT.get_or_init(Client::new)
+}
+
+impl ProxyConfig {
+    /// Header names and values are checked once, when the config is loaded.
+    pub fn validate(&self) -> Result<(), String> {
+        validate_headers(&self.headers)?;
+        validate_headers(&self.response_headers)?;
+        for name in &self.remove_headers {
+            HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| format!("invalid header name: {name}"))?;
+        }
+        Ok(())
+    }
+}
+
+/// Forward the request upstream and stream the response back. `timeout`
+/// bounds the hook, the upstream request has a timeout of its own.
+pub async fn proxy_request(
+    config: &ProxyConfig,
+    router: &AppRouter,
+    services: ServiceContext,
+    params: &HashMap<String, String>,
+    req: Request,
+    body_limit: usize,
+    timeout: Option<Duration>,
+) -> Result<Response, AppError> {
+    let (parts, body) = req.into_parts();
+    let body = crate::read_body(body, body_limit).await?;
+
+    check_url_params(&config.to, params)?;
+    let mut url = substitute(&config.to, params);
+    if let (false, Some(q)) = (url.contains('?'), parts.uri.query()) {
+        url = format!("{url}?{q}");
+    }
+
+    let mut headers = parts.headers.clone();
+    for name in HOP_BY_HOP.iter().chain([&HOST, &CONTENT_LENGTH]) {
+        headers.remove(name);
+    }
+    for name in FORWARDED {
+        headers.remove(name);
+    }
+    let conn = parts.extensions.get::<ConnInfo>();
+    if let Some(peer) = conn.and_then(|v| v.peer) {
+        if let Ok(v) = HeaderValue::from_str(&peer.ip().to_string()) {
+            headers.insert(X_FORWARDED_FOR, v);
+        }
+    }
+    if let Some(host) = parts.headers.get(HOST) {
+        headers.insert(X_FORWARDED_HOST, host.clone());
+    }
+    let proto = ConnInfo::scheme(conn);
+    headers.insert(FORWARDED_PROTO, HeaderValue::from_static(proto));
+    for name in &config.remove_headers {
+        headers.remove(name.as_str());
+    }
+    for (k, v) in &config.headers {
+        headers.insert(
+            HeaderName::from_bytes(k.as_bytes()).map_err(|e| anyhow!(e))?,
+            HeaderValue::from_str(&substitute(v, params)).map_err(|e| anyhow!(e))?,
+        );
+    }
+
+    let mut upstream = Upstream {
+        method: parts.method,
+        url,
+        headers,
+        body,
+    };
+    if let Some(hook) = &config.hook {
+        upstream = run_hook(router, services, hook, upstream, timeout).await?;
+    }
+
+    let res = send(config, upstream).await?;
+    let mut builder = Response::builder().status(res.status().as_u16());
+    for (k, v) in res.headers() {
+        if !HOP_BY_HOP.contains(k) {
+            builder = builder.header(k.as_str(), v.as_bytes());
+        }
+    }
+    for (k, v) in &config.response_headers {
+        builder = builder.header(k.as_str(), v.as_str());
+    }
+    Ok(builder
+        .body(Body::from_stream(res.bytes_stream()))
+        .map_err(|e| anyhow!(e))?)
+}
+
+async fn send(config: &ProxyConfig, upstream: Upstream) -> Result<reqwest::Response, AppError> {
+    // only requests that are safe to repeat are retried, and only if they
+    // never reached the upstream
+    let attempts = if upstream.method.is_idempotent() {
+        config.retries + 1
+    } else {
+        1
+    };
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let mut req = client()
+            .request(upstream.method.clone(), &upstream.url)
+            .headers(upstream.headers.clone())
+            .body(upstream.body.clone());
+        if let Some(timeout) = config.timeout {
+            req = req.timeout(timeout);
+        }
+        match req.send().await {
+            Ok(res) => return Ok(res),
+            Err(e) if e.is_connect() && attempt < attempts => {
+                warn!("proxy to {} failed, retrying: {}", upstream.url, e);
+            }
+            Err(e) if e.is_timeout() => return Err(AppError::UpstreamTimeout(upstream.url)),
+            Err(e) => return Err(AppError::BadGateway(e.to_string())),
+        }
+    }
+}
+
+// params come from the raw path, a `..` segment, plain or encoded, would
+// climb out of the upstream path once it's normalized
+fn check_url_params(to: &str, params: &HashMap<String, String>) -> Result<(), AppError> {
+    let used = params
+        .iter()
+        .filter(|(k, _)| to.contains(&format!(":{k}")) || to.contains(&format!("*{k}")));
+    for (_, v) in used {
+        let bad = v.split('/').any(|segment| {
+            let decoded = percent_decode_str(segment).decode_utf8_lossy();
+            matches!(&*decoded, "." | "..") || decoded.contains(['/', '\\'])
+        });
+        if bad {
+            return Err(AppError::InvalidPathParam(v.clone()));
+        }
+    }
+    Ok(())
+}
+
+async fn run_hook(
+    router: &AppRouter,
+    services: ServiceContext,
+    hook: &str,
+    upstream: Upstream,
+    timeout: Option<Duration>,
+) -> Result<Upstream, AppError> {
+    let headers = upstream
+        .headers
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_str().unwrap_or_default().to_string()))
+        .collect();
+    let req = Req::builder()
+        .method(upstream.method.to_string())
+        .url(upstream.url)
+        .query(HashMap::new())
+        .params(HashMap::new())
+        .headers(headers)
+        .body(
+            (!upstream.body.is_empty())
+                .then(|| String::from_utf8_lossy(&upstream.body).to_string()),
+        )
+        .raw_body(None)
+        .build();
+
+    let router = router.clone();
+    let hook = hook.to_string();
+    let ret: ServiceReq = spawn_blocking(move || {
+        // the hook is stopped with the rest of the js once the server is done draining
+        let worker = JsWorker::try_new_with_interrupt(&router.code, timeout, services.drain())?;
+        worker.bind_services(&router.services, services)?;
+        worker.run_hook(&hook, req)
+    })
+    .await
+    .map_err(|e| anyhow!(e))??;
+
+    let mut headers = HeaderMap::new();
+    for (k, v) in ret.headers {
+        headers.insert(
+            HeaderName::from_bytes(k.as_bytes()).map_err(|e| anyhow!(e))?,
+            HeaderValue::from_str(&v).map_err(|e| anyhow!(e))?,
+        );
+    }
+    let method = ret.method.to_uppercase();
+    Ok(Upstream {
+        method: Method::from_bytes(method.as_bytes()).map_err(|e| anyhow!(e))?,
+        url: ret.url,
+        headers,
+        // a hook that doesn't touch the body keeps the original bytes
+        body: ret.body.map(Bytes::from).unwrap_or(upstream.body),
+    })
+}
+
+/// `proxy:` is either the upstream url or the full config.
+pub(crate) fn deserialize_proxy<'de, D>(deserializer: D) -> Result<Option<ProxyConfig>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Proxy {
+        Url(String),
+        Config(Box<ProxyConfig>),
+    }
+
+    Ok(match Proxy::deserialize(deserializer)? {
+        Proxy::Url(to) => Some(ProxyConfig {
+            to,
+            headers: IndexMap::new(),
+            remove_headers: vec![],
+            response_headers: IndexMap::new(),
+            timeout: None,
+            retries: 0,
+            hook: None,
+        }),
+        Proxy::Config(v) => Some(*v),
+    })
+}
+
+pub(crate) fn proxy_schema(gen: &mut SchemaGenerator) -> Schema {
+    SchemaObject {
+        subschemas: Some(Box::new(SubschemaValidation {
+            any_of: Some(vec![
+                gen.subschema_for::<String>(),
+                gen.subschema_for::<ProxyConfig>(),
+            ]),
+            ..Default::default()
+        })),
+        ..Default::default()
+    }
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ProjectConfig, SwappableAppRouter};
+    use axum::{
+        extract::Path,
+        http::{HeaderMap, StatusCode},
+        routing::get,
+        Router,
+    };
+    use tokio::net::TcpListener;
+
+    async fn upstream() -> String {
+        async fn echo(Path(id): Path<String>, headers: HeaderMap) -> String {
+            let auth = headers.get("x-auth").and_then(|v| v.to_str().ok());
+            let cookie = headers.get("cookie").is_some();
+            format!("{id} {auth:?} {cookie}")
+        }
+        async fn forwarded(headers: HeaderMap) -> String {
+            let get = |k: &str| headers.get(k).map(|v| v.to_str().unwrap().to_string());
+            format!("{:?} {:?}", get(X_FORWARDED_FOR), get(FORWARDED_PROTO))
+        }
+        async fn slow() -> &'static str {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            "slow"
+        }
+
+        let app = Router::new()
+            .route("/users/:id", get(echo))
+            .route("/forwarded", get(forwarded))
+            .route("/slow", get(slow));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        format!("http://{addr}")
+    }
+
+    fn router(upstream: &str) -> AppRouter {
+        let code = r#"
+    (function(){
+        function auth(req) {
+            req.headers["x-auth"] = "from-hook";
+            return req;
+        }
+        return { auth: auth };
+    })();
+    "#;
+        let config = format!(
+            r#"
+name: test
+routes:
+  /api/:id:
+    - method: GET
+      proxy:
+        to: {upstream}/users/:id
+        headers:
+          x-auth: user-:id
+        remove_headers: [cookie]
+        response_headers:
+          x-proxy: dino
+  /hook/:id:
+    - method: GET
+      proxy:
+        to: {upstream}/users/:id
+        hook: auth
+  /files/*rest:
+    - method: GET
+      proxy: {upstream}/users/*rest
+  /forwarded:
+    - method: GET
+      proxy: {upstream}/forwarded
+  /slow:
+    - method: GET
+      proxy:
+        to: {upstream}/slow
+        timeout: 50ms
+        retries: 1
+"#
+        );
+        let config: ProjectConfig = serde_yaml::from_str(&config).unwrap();
+        SwappableAppRouter::try_new(code, config).unwrap().load()
+    }
+
+    async fn call(router: &AppRouter, path: &str) -> Result<Response, AppError> {
+        let m = router.match_it(Method::GET, path)?;
+        let params = crate::match_params(&m);
+        let crate::RouteAction::Proxy(config) = m.value.action.clone() else {
+            panic!("should be a proxy route");
+        };
+        let req = Request::builder()
+            .uri(path)
+            .header("cookie", "session=1")
+            .header(X_FORWARDED_FOR, "6.6.6.6")
+            .extension(ConnInfo {
+                peer: Some(([10, 0, 0, 1], 4000).into()),
+                secure: true,
+            })
+            .body(Body::empty())
+            .unwrap();
+        let services = ServiceContext::new(Default::default());
+        proxy_request(&config, router, services, &params, req, 1024, None).await
+    }
+
+    async fn text(res: Response) -> String {
+        let body = axum::body::to_bytes(res.into_body(), 1024).await.unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn proxy_should_forward_requests() {
+        let router = router(&upstream().await);
+
+        let res = call(&router, "/api/42").await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["x-proxy"], "dino");
+        assert_eq!(text(res).await, r#"42 Some("user-42") false"#);
+
+        let res = call(&router, "/hook/7").await.unwrap();
+        assert_eq!(text(res).await, r#"7 Some("from-hook") true"#);
+
+        let ret = call(&router, "/slow").await;
+        assert!(matches!(ret, Err(AppError::UpstreamTimeout(_))));
+    }
+
+    #[tokio::test]
+    async fn proxy_should_set_forwarded_headers_from_the_connection() {
+        let router = router(&upstream().await);
+        let res = call(&router, "/forwarded").await.unwrap();
+        assert_eq!(text(res).await, r#"Some("10.0.0.1") Some("https")"#);
+    }
+
+    #[tokio::test]
+    async fn proxy_should_reject_dot_segments() {
+        let router = router(&upstream().await);
+        let res = call(&router, "/files/42").await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        for path in [
+            "/files/../admin",
+            "/files/a/%2e%2e/admin",
+            "/files/a%2F..%2Fadmin",
+        ] {
+            let ret = call(&router, path).await;
+            assert!(matches!(ret, Err(AppError::InvalidPathParam(_))), "{path}");
+        }
+    }
+
+    #[test]
+    fn proxy_config_should_validate_headers() {
+        let config: ProxyConfig =
+            serde_yaml::from_str("to: http://a\nheaders:\n  bad name: x\n").unwrap();
+        assert!(config.validate().is_err());
+        let config: ProxyConfig =
+            serde_yaml::from_str("to: http://a\nremove_headers: [\"a:b\"]\n").unwrap();
+        assert!(config.validate().is_err());
+    }
+}
@@ -0,0 +1,79 @@
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+
+/// The query string of a request. Parsing never fails, malformed escapes are
+/// kept as is, so a bad query never rejects the request.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueryString {
+    // undecoded, without the leading `?`
+    pub raw: String,
+    // every value of each key, in the order they were sent
+    pub all: HashMap<String, Vec<String>>,
+}
+
+impl QueryString {
+    pub fn parse(raw: Option<&str>) -> Self {
+        let raw = raw.unwrap_or_default();
+        let mut all: HashMap<String, Vec<String>> = HashMap::new();
+        for (k, v) in form_urlencoded::parse(raw.as_bytes()) {
+            all.entry(k.into_owned()).or_default().push(v.into_owned());
+        }
+        Self {
+            raw: raw.to_string(),
+            all,
+        }
+    }
+
+    /// One value per key, the last one wins for repeated keys.
+    pub fn last(&self) -> HashMap<String, String> {
+        self.all
+            .iter()
+            .filter_map(|(k, v)| Some((k.clone(), v.last()?.clone())))
+            .collect()
+    }
+
+    /// Keys sent once are strings, repeated keys and `arrays` are arrays of
+    /// strings.
+    pub fn to_json(&self, arrays: &HashSet<String>) -> Value {
+        self.all
+            .iter()
+            .map(|(k, v)| {
+                let v = match v.as_slice() {
+                    [one] if !arrays.contains(k) => Value::String(one.clone()),
+                    _ => v.clone().into(),
+                };
+                (k.clone(), v)
+            })
+            .collect::<serde_json::Map<_, _>>()
+            .into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn query_string_should_keep_repeated_keys() {
+        let q = QueryString::parse(Some("tag=a&tag=b&q=hello%20world&empty"));
+        assert_eq!(q.raw, "tag=a&tag=b&q=hello%20world&empty");
+        assert_eq!(q.all["tag"], ["a", "b"]);
+        assert_eq!(q.last()["tag"], "b");
+        assert_eq!(
+            q.to_json(&HashSet::new()),
+            json!({ "tag": ["a", "b"], "q": "hello world", "empty": "" })
+        );
+        let arrays = HashSet::from(["q".to_string()]);
+        assert_eq!(q.to_json(&arrays)["q"], json!(["hello world"]));
+    }
+
+    #[test]
+    fn query_string_should_accept_malformed_input() {
+        let q = QueryString::parse(Some("a=%zz&=1&&b=%E4"));
+        assert_eq!(q.all["a"], ["%zz"]);
+        assert_eq!(q.all[""], ["1"]);
+        assert_eq!(q.all["b"], ["\u{fffd}"]);
+        assert_eq!(QueryString::parse(None), QueryString::default());
+    }
+}
@@ -1,18 +1,45 @@
-use crate::{AppError, ProjectRoutes};
-use anyhow::Result;
-use arc_swap::ArcSwap;
-use axum::http::Method;
+use crate::{
+    canary::Canary, history::History, metrics::metrics, openapi_document, shadow::Shadow,
+    substitute, AppError, Assets, BodyLimits, CanaryRule, CanaryStatus, OpenApi, PathMatching,
+    ProjectConfig, ProjectRoutes, RequestLimits, RequestValidator, RouteAction, RouteMethod,
+    RouteOptions, Selection, ShadowRule, ShadowStatus, TrailingSlash, VersionInfo,
+};
+use anyhow::{anyhow, Result};
+use arc_swap::{ArcSwap, ArcSwapOption};
+use axum::http::{HeaderMap, Method};
+use indexmap::IndexMap;
 use matchit::{Match, Router};
-use std::{ops::Deref, sync::Arc};
+use std::{
+    collections::HashMap,
+    ops::Deref,
+    sync::{Arc, Mutex},
+};
+
+// a rewrite may target another rewrite, but not forever
+const MAX_REWRITES: usize = 8;
 
 #[derive(Clone)]
 pub struct SwappableAppRouter {
     pub inner: Arc<ArcSwap<AppRouterInner>>,
+    // recent versions, shared by every host serving this router
+    history: Arc<Mutex<History>>,
+    // served next to the current version for part of the traffic
+    canary: Arc<ArcSwapOption<Canary>>,
+    // gets a copy of sampled requests, its responses are only compared
+    shadow: Arc<ArcSwapOption<Shadow>>,
 }
 
 pub struct AppRouterInner {
+    // hash of the build, defaults to a hash of the code
+    pub hash: String,
     pub code: String,
     pub router: Router<MethodRoute>,
+    pub body_limits: BodyLimits,
+    pub services: IndexMap<String, String>,
+    pub assets: Option<Assets>,
+    pub limits: RequestLimits,
+    pub openapi: Option<OpenApi>,
+    pub matching: PathMatching,
 }
 
 #[derive(Clone)]
@@ -20,53 +47,194 @@ pub struct AppRouter(Arc<AppRouterInner>);
 
 #[derive(Debug, Default, Clone)]
 pub struct MethodRoute {
-    get: Option<String>, // handler name in js code
-    head: Option<String>,
-    delete: Option<String>,
-    options: Option<String>,
-    patch: Option<String>,
-    post: Option<String>,
-    put: Option<String>,
-    trace: Option<String>,
-    connect: Option<String>,
+    get: Option<RouteEndpoint>,
+    head: Option<RouteEndpoint>,
+    delete: Option<RouteEndpoint>,
+    options: Option<RouteEndpoint>,
+    patch: Option<RouteEndpoint>,
+    post: Option<RouteEndpoint>,
+    put: Option<RouteEndpoint>,
+    trace: Option<RouteEndpoint>,
+    connect: Option<RouteEndpoint>,
+    // extension methods like PROPFIND
+    custom: HashMap<Method, RouteEndpoint>,
+    // fallback for every method without its own handler
+    any: Option<RouteEndpoint>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RouteEndpoint {
+    // the route pattern, e.g. `/users/{id}`
+    pub path: String,
+    pub action: RouteAction,
+    pub limits: RequestLimits,
+    pub options: RouteOptions,
+    pub validator: Option<Arc<RequestValidator>>,
+}
+
+impl From<AppRouterInner> for SwappableAppRouter {
+    fn from(inner: AppRouterInner) -> Self {
+        let inner = Arc::new(inner);
+        let mut history = History::default();
+        history.push(inner.clone(), "initial");
+        Self {
+            inner: Arc::new(ArcSwap::new(inner)),
+            history: Arc::new(Mutex::new(history)),
+            canary: Arc::new(ArcSwapOption::empty()),
+            shadow: Arc::new(ArcSwapOption::empty()),
+        }
+    }
 }
 
 impl SwappableAppRouter {
-    pub fn try_new(code: impl Into<String>, routes: ProjectRoutes) -> Result<Self> {
-        let router = Self::get_router(routes)?;
-        let inner = AppRouterInner::new(code, router);
-        Ok(Self {
-            inner: Arc::new(ArcSwap::from_pointee(inner)),
-        })
+    pub fn try_new(code: impl Into<String>, config: ProjectConfig) -> Result<Self> {
+        let inner = AppRouterInner::try_new(code, config)?;
+        Ok(inner.into())
     }
 
-    pub fn swap(&self, code: impl Into<String>, routes: ProjectRoutes) -> Result<()> {
-        let router = Self::get_router(routes)?;
-        let inner = AppRouterInner::new(code, router);
-        self.inner.store(Arc::new(inner));
+    pub fn swap(&self, code: impl Into<String>, config: ProjectConfig) -> Result<()> {
+        let inner = AppRouterInner::try_new(code, config)?;
+        self.store(inner, "swap");
         Ok(())
     }
 
+    /// Swap in a router that is already built, e.g. one with its own build hash.
+    /// `source` tells where it came from in the version history.
+    pub fn store(&self, mut inner: AppRouterInner, source: &str) {
+        inner.share_assets(&self.inner.load());
+        self.store_arc(Arc::new(inner), source);
+    }
+
+    fn store_arc(&self, inner: Arc<AppRouterInner>, source: &str) -> VersionInfo {
+        let mut history = self.history.lock().unwrap();
+        let info = history.push(inner.clone(), source);
+        self.inner.store(inner);
+        metrics().swaps.with_label_values(&[source]).inc();
+        info
+    }
+
+    /// Recent versions, newest first.
+    pub fn versions(&self) -> Vec<VersionInfo> {
+        let history = self.history.lock().unwrap();
+        history.list(&self.inner.load_full())
+    }
+
+    /// Go back to the version with the given build hash, or to the one before
+    /// the current version. Old versions are kept built, so nothing is rebuilt.
+    pub fn rollback(&self, to: Option<&str>) -> Result<VersionInfo, AppError> {
+        let history = self.history.lock().unwrap();
+        let (inner, info) = history
+            .find(to, &self.inner.load_full())
+            .ok_or_else(|| AppError::VersionNotFound(to.unwrap_or("previous").to_string()))?;
+        self.inner.store(inner);
+        metrics().swaps.with_label_values(&["rollback"]).inc();
+        Ok(info)
+    }
+
     pub fn load(&self) -> AppRouter {
         AppRouter(self.inner.load_full())
     }
 
-    fn get_router(routes: ProjectRoutes) -> Result<Router<MethodRoute>> {
+    /// The version serving a request: the canary if its rule picks the
+    /// request, the current version otherwise.
+    pub fn select(&self, headers: &HeaderMap) -> Selection {
+        let stable = self.load();
+        let canary = self.canary.load();
+        let Some(canary) = canary.as_ref() else {
+            return Selection {
+                router: stable,
+                canary: false,
+                cookie: None,
+            };
+        };
+        let (is_canary, cookie) = canary.choose(headers, &stable.hash);
+        let router = match is_canary {
+            true => AppRouter(canary.router.clone()),
+            false => stable,
+        };
+        Selection {
+            router,
+            canary: is_canary,
+            cookie,
+        }
+    }
+
+    /// Serve `inner` next to the current version for the requests `rule` picks.
+    pub fn set_canary(&self, mut inner: AppRouterInner, rule: CanaryRule) -> Result<(), AppError> {
+        rule.validate().map_err(AppError::InvalidConfig)?;
+        inner.share_assets(&self.inner.load());
+        let canary = Canary::new(Arc::new(inner), rule);
+        self.canary.store(Some(Arc::new(canary)));
+        Ok(())
+    }
+
+    pub fn canary(&self) -> Option<CanaryStatus> {
+        let stable = self.inner.load();
+        self.canary.load().as_ref().map(|v| v.status(&stable.hash))
+    }
+
+    /// Stop the canary, every request goes to the current version again.
+    pub fn clear_canary(&self) -> Option<CanaryStatus> {
+        let stable = self.inner.load();
+        self.canary.swap(None).map(|v| v.status(&stable.hash))
+    }
+
+    /// Make the canary the current version for all traffic.
+    pub fn promote_canary(&self) -> Result<VersionInfo, AppError> {
+        let canary = self
+            .canary
+            .swap(None)
+            .ok_or_else(|| AppError::VersionNotFound("canary".to_string()))?;
+        Ok(self.store_arc(canary.router.clone(), "canary"))
+    }
+
+    /// Mirror requests picked by `rule` to `inner` and report how its responses
+    /// differ from the live ones.
+    pub fn set_shadow(&self, inner: AppRouterInner, rule: ShadowRule) -> Result<(), AppError> {
+        rule.validate().map_err(AppError::InvalidConfig)?;
+        let shadow = Shadow::new(AppRouter(Arc::new(inner)), rule);
+        self.shadow.store(Some(Arc::new(shadow)));
+        Ok(())
+    }
+
+    pub fn shadow(&self) -> Option<ShadowStatus> {
+        self.shadow.load().as_ref().map(|v| v.status())
+    }
+
+    pub fn clear_shadow(&self) -> Option<ShadowStatus> {
+        self.shadow.swap(None).map(|v| v.status())
+    }
+
+    // the shadow, if this request is sampled for it
+    pub(crate) fn sample_shadow(&self, method: &Method) -> Option<Arc<Shadow>> {
+        self.shadow.load_full().filter(|v| v.sample(method))
+    }
+
+    fn get_router(routes: ProjectRoutes, case_insensitive: bool) -> Result<Router<MethodRoute>> {
         let mut router = Router::new();
         for (path, methods) in routes {
+            // request paths are lowercased too, see `AppRouter::route_path`
+            let path = match case_insensitive {
+                true => lowercase_statics(&path),
+                false => path,
+            };
             let mut method_route = MethodRoute::default();
-            for method in methods {
-                match method.method {
-                    Method::GET => method_route.get = Some(method.handler),
-                    Method::HEAD => method_route.head = Some(method.handler),
-                    Method::DELETE => method_route.delete = Some(method.handler),
-                    Method::OPTIONS => method_route.options = Some(method.handler),
-                    Method::PATCH => method_route.patch = Some(method.handler),
-                    Method::POST => method_route.post = Some(method.handler),
-                    Method::PUT => method_route.put = Some(method.handler),
-                    Method::TRACE => method_route.trace = Some(method.handler),
-                    Method::CONNECT => method_route.connect = Some(method.handler),
-                    v => unreachable!("unsupported method {v}"),
+            for route in methods {
+                let action = route
+                    .action()
+                    .map_err(|e| anyhow!("route {}: {}", path, e))?;
+                // schemas are compiled once per swap
+                let validator = RequestValidator::try_new(&route)
+                    .map_err(|e| anyhow!("route {}: {:#}", path, e))?;
+                let endpoint = RouteEndpoint {
+                    path: path.clone(),
+                    action,
+                    limits: route.limits,
+                    options: route.options,
+                    validator: validator.map(Arc::new),
+                };
+                for method in route.methods {
+                    method_route.set(method, endpoint.clone());
                 }
             }
             router.insert(path, method_route)?;
@@ -76,11 +244,53 @@ impl SwappableAppRouter {
 }
 
 impl AppRouter {
+    /// The path routes are matched against per the `matching:` options, and
+    /// where to redirect the client if the path only matches once its trailing
+    /// slash is added or removed.
+    pub fn route_path(&self, path: &str) -> (String, Option<String>) {
+        let route = match self.matching.case_insensitive {
+            true => path.to_ascii_lowercase(),
+            false => path.to_string(),
+        };
+        let trailing_slash = self.matching.trailing_slash;
+        if trailing_slash == TrailingSlash::Strict || self.router.at(&route).is_ok() {
+            return (route, None);
+        }
+        let other = toggle_slash(&route);
+        if self.router.at(&other).is_err() {
+            return (route, None);
+        }
+        let redirect = (trailing_slash == TrailingSlash::Redirect).then(|| toggle_slash(path));
+        (other, redirect)
+    }
+
+    /// Follow `rewrite:` routes, returns the internal target if there was any.
+    /// The target may carry its own query string.
+    pub fn rewrite(&self, method: &Method, path: &str) -> Result<Option<String>, AppError> {
+        let mut target: Option<String> = None;
+        for _ in 0..MAX_REWRITES {
+            let current = target.as_deref().unwrap_or(path);
+            let current = current.split('?').next().unwrap_or_default();
+            let next = match self.match_it(method.clone(), current) {
+                Ok(m) => match &m.value.action {
+                    RouteAction::Rewrite(to) => Some(substitute(to, &match_params(&m))),
+                    _ => None,
+                },
+                Err(_) => None,
+            };
+            match next {
+                Some(v) => target = Some(v),
+                None => return Ok(target),
+            }
+        }
+        Err(AppError::TooManyRewrites(path.to_string()))
+    }
+
     pub fn match_it<'m, 'p>(
         &'m self,
         method: Method,
         path: &'p str,
-    ) -> Result<Match<&str>, AppError>
+    ) -> Result<Match<&RouteEndpoint>, AppError>
     where
         'p: 'm,
     {
@@ -88,19 +298,10 @@ impl AppRouter {
             return Err(AppError::RoutePathNotFound(path.to_string()));
         };
 
-        let s = match method {
-            Method::GET => ret.value.get.as_deref(),
-            Method::HEAD => ret.value.head.as_deref(),
-            Method::DELETE => ret.value.delete.as_deref(),
-            Method::OPTIONS => ret.value.options.as_deref(),
-            Method::PATCH => ret.value.patch.as_deref(),
-            Method::POST => ret.value.post.as_deref(),
-            Method::PUT => ret.value.put.as_deref(),
-            Method::TRACE => ret.value.trace.as_deref(),
-            Method::CONNECT => ret.value.connect.as_deref(),
-            _ => unreachable!(),
-        }
-        .ok_or_else(|| AppError::RouteMethodNotAllowed(method))?;
+        let s = ret
+            .value
+            .get(&method)
+            .ok_or_else(|| AppError::RouteMethodNotAllowed(method, ret.value.allowed()))?;
         Ok(Match {
             value: s,
             params: ret.params,
@@ -108,6 +309,116 @@ impl AppRouter {
     }
 }
 
+/// Path params of a match, taken from the request `path` so they keep their
+/// case. A param is a whole segment, or the rest of the path for `*name`.
+pub(crate) fn request_params(
+    matched: &Match<&RouteEndpoint>,
+    path: &str,
+) -> HashMap<String, String> {
+    let mut params = match_params(matched);
+    let mut keep_case = |name: &str, v: &str| {
+        // the matched path may differ by more than case, e.g. its trailing slash
+        if let Some(param) = params.get_mut(name).filter(|p| p.eq_ignore_ascii_case(v)) {
+            *param = v.to_string();
+        }
+    };
+    let mut offset = 0;
+    for (pattern, segment) in matched.value.path.split('/').zip(path.split('/')) {
+        if let Some(name) = pattern.strip_prefix(':') {
+            keep_case(name, segment);
+        } else if let Some(name) = pattern.strip_prefix('*') {
+            keep_case(name, &path[offset..]);
+            break;
+        }
+        offset += segment.len() + 1;
+    }
+    params
+}
+
+/// Path params of a match, owned.
+pub(crate) fn match_params(matched: &Match<&RouteEndpoint>) -> HashMap<String, String> {
+    matched
+        .params
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+impl RouteEndpoint {
+    /// Name of the js handler, if the route calls one.
+    pub fn handler(&self) -> Option<&str> {
+        match &self.action {
+            RouteAction::Handler(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+impl MethodRoute {
+    pub fn get(&self, method: &Method) -> Option<&RouteEndpoint> {
+        let endpoint = match *method {
+            Method::GET => self.get.as_ref(),
+            // HEAD is derived from GET when there is no dedicated handler
+            Method::HEAD => self.head.as_ref().or(self.get.as_ref()),
+            Method::DELETE => self.delete.as_ref(),
+            Method::OPTIONS => self.options.as_ref(),
+            Method::PATCH => self.patch.as_ref(),
+            Method::POST => self.post.as_ref(),
+            Method::PUT => self.put.as_ref(),
+            Method::TRACE => self.trace.as_ref(),
+            Method::CONNECT => self.connect.as_ref(),
+            _ => self.custom.get(method),
+        };
+        endpoint.or(self.any.as_ref())
+    }
+
+    fn set(&mut self, method: RouteMethod, endpoint: RouteEndpoint) {
+        let slot = match method {
+            RouteMethod::Any => &mut self.any,
+            RouteMethod::Method(m) => match m {
+                Method::GET => &mut self.get,
+                Method::HEAD => &mut self.head,
+                Method::DELETE => &mut self.delete,
+                Method::OPTIONS => &mut self.options,
+                Method::PATCH => &mut self.patch,
+                Method::POST => &mut self.post,
+                Method::PUT => &mut self.put,
+                Method::TRACE => &mut self.trace,
+                Method::CONNECT => &mut self.connect,
+                m => {
+                    self.custom.insert(m, endpoint);
+                    return;
+                }
+            },
+        };
+        *slot = Some(endpoint);
+    }
+
+    /// Methods served on this path, HEAD and OPTIONS are always answered.
+    pub fn allowed(&self) -> Vec<Method> {
+        let methods = [
+            (Method::GET, &self.get),
+            (Method::HEAD, &self.head),
+            (Method::POST, &self.post),
+            (Method::PUT, &self.put),
+            (Method::PATCH, &self.patch),
+            (Method::DELETE, &self.delete),
+            (Method::TRACE, &self.trace),
+            (Method::CONNECT, &self.connect),
+        ];
+        let mut allowed: Vec<Method> = methods
+            .into_iter()
+            .filter(|(m, v)| v.is_some() || (m == Method::HEAD && self.get.is_some()))
+            .map(|(m, _)| m)
+            .collect();
+        let mut custom: Vec<Method> = self.custom.keys().cloned().collect();
+        custom.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+        allowed.extend(custom);
+        allowed.push(Method::OPTIONS);
+        allowed
+    }
+}
+
 impl Deref for AppRouter {
     type Target = AppRouterInner;
 
@@ -117,52 +428,299 @@ impl Deref for AppRouter {
 }
 
 impl AppRouterInner {
-    pub fn new(code: impl Into<String>, router: Router<MethodRoute>) -> Self {
-        Self {
-            code: code.into(),
+    pub fn try_new(code: impl Into<String>, config: ProjectConfig) -> Result<Self> {
+        let code = code.into();
+        // config only deploys are new versions too. Config types keep their
+        // order, so the debug output is stable for the same config
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(code.as_bytes());
+        hasher.update(format!("{config:?}").as_bytes());
+        let mut hash = hasher.finalize().to_string();
+        hash.truncate(16);
+        // the document is built before the routes are consumed
+        let openapi = match &config.openapi {
+            Some(v) => Some(OpenApi {
+                path: v.path.clone(),
+                document: openapi_document(&config)?,
+            }),
+            None => None,
+        };
+        let router =
+            SwappableAppRouter::get_router(config.routes, config.matching.case_insensitive)?;
+        let assets = config.assets.as_ref().map(Assets::load).transpose()?;
+        Ok(Self {
+            hash,
+            code,
             router,
+            body_limits: config.body,
+            services: config.services,
+            assets,
+            limits: config.limits,
+            openapi,
+            matching: config.matching,
+        })
+    }
+
+    // files that didn't change since `other` point to the same content
+    fn share_assets(&mut self, other: &AppRouterInner) {
+        if let (Some(assets), Some(other)) = (&mut self.assets, &other.assets) {
+            assets.share(other);
         }
     }
 }
 
+// `/Users/:userId` => `/users/:userId`
+fn lowercase_statics(path: &str) -> String {
+    path.split('/')
+        .map(|s| match s.starts_with([':', '*']) {
+            true => s.to_string(),
+            false => s.to_ascii_lowercase(),
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+// `/users/` <=> `/users`, the root path stays as is
+fn toggle_slash(path: &str) -> String {
+    match path.strip_suffix('/') {
+        Some("") => path.to_string(),
+        Some(v) => v.to_string(),
+        None => format!("{path}/"),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::ProjectConfig;
+    use crate::{ProjectConfig, MAX_VERSIONS};
 
     #[test]
     fn app_router_match_should_work() {
         let config = include_str!("../fixtures/config.yml");
         let config: ProjectConfig = serde_yaml::from_str(config).unwrap();
-        let router = SwappableAppRouter::try_new("", config.routes).unwrap();
+        let router = SwappableAppRouter::try_new("", config).unwrap();
         let app_router = router.load();
         let m = app_router.match_it(Method::GET, "/api/hello/1").unwrap();
-        assert_eq!(m.value, "hello");
+        assert_eq!(m.value.handler(), Some("hello"));
         assert_eq!(m.params.get("id"), Some("1"));
 
         let m = app_router.match_it(Method::POST, "/api/goodbye/2").unwrap();
-        assert_eq!(m.value, "hello");
+        assert_eq!(m.value.handler(), Some("hello"));
         assert_eq!(m.params.get("id"), Some("2"));
         assert_eq!(m.params.get("name"), Some("goodbye"));
     }
 
+    #[test]
+    fn app_router_should_derive_head_and_allow() {
+        let config = include_str!("../fixtures/config.yml");
+        let config: ProjectConfig = serde_yaml::from_str(config).unwrap();
+        let router = SwappableAppRouter::try_new("", config).unwrap();
+        let app_router = router.load();
+        let m = app_router.match_it(Method::HEAD, "/api/hello/1").unwrap();
+        assert_eq!(m.value.handler(), Some("hello"));
+
+        let Err(AppError::RouteMethodNotAllowed(_, allowed)) =
+            app_router.match_it(Method::DELETE, "/api/hello/1")
+        else {
+            panic!("DELETE should not be allowed");
+        };
+        assert_eq!(
+            allowed,
+            [Method::GET, Method::HEAD, Method::POST, Method::OPTIONS]
+        );
+    }
+
+    #[test]
+    fn app_router_should_support_any_and_custom_methods() {
+        let config = r#"
+name: test
+routes:
+  /dav/*path:
+    - method: PROPFIND
+      handler: propfind
+    - method: [GET, PUT]
+      handler: file
+  /any:
+    - method: ANY
+      handler: any
+"#;
+        let config: ProjectConfig = serde_yaml::from_str(config).unwrap();
+        let router = SwappableAppRouter::try_new("", config).unwrap();
+        let app_router = router.load();
+        let propfind = Method::from_bytes(b"PROPFIND").unwrap();
+        let m = app_router.match_it(propfind.clone(), "/dav/a.txt").unwrap();
+        assert_eq!(m.value.handler(), Some("propfind"));
+        let m = app_router.match_it(Method::PUT, "/dav/a.txt").unwrap();
+        assert_eq!(m.value.handler(), Some("file"));
+
+        let mkcol = Method::from_bytes(b"MKCOL").unwrap();
+        let Err(AppError::RouteMethodNotAllowed(_, allowed)) =
+            app_router.match_it(mkcol.clone(), "/dav/a.txt")
+        else {
+            panic!("MKCOL should not be allowed");
+        };
+        assert_eq!(
+            allowed,
+            [
+                Method::GET,
+                Method::HEAD,
+                Method::PUT,
+                propfind,
+                Method::OPTIONS
+            ]
+        );
+
+        let m = app_router.match_it(mkcol, "/any").unwrap();
+        assert_eq!(m.value.handler(), Some("any"));
+        let m = app_router.match_it(Method::DELETE, "/any").unwrap();
+        assert_eq!(m.value.handler(), Some("any"));
+    }
+
+    #[test]
+    fn app_router_should_normalize_paths() {
+        let config = r#"
+name: test
+matching:
+  trailing_slash: redirect
+  case_insensitive: true
+routes:
+  /Users/:userId:
+    - method: GET
+      handler: getUser
+  /teams/:id/:
+    - method: GET
+      handler: getTeam
+  /Files/*rest:
+    - method: GET
+      handler: getFile
+"#;
+        let config: ProjectConfig = serde_yaml::from_str(config).unwrap();
+        let router = SwappableAppRouter::try_new("", config).unwrap().load();
+
+        let path = "/USERS/AbC";
+        let (route, redirect) = router.route_path(path);
+        assert_eq!((route.as_str(), redirect), ("/users/abc", None));
+        let m = router.match_it(Method::GET, &route).unwrap();
+        let params = request_params(&m, path);
+        assert_eq!(params["userId"], "AbC");
+
+        let path = "/files/Docs/A.txt";
+        let (route, _) = router.route_path(path);
+        let m = router.match_it(Method::GET, &route).unwrap();
+        assert_eq!(request_params(&m, path)["rest"], "Docs/A.txt");
+
+        let (route, redirect) = router.route_path("/Teams/1");
+        assert_eq!(route, "/teams/1/");
+        assert_eq!(redirect.as_deref(), Some("/Teams/1/"));
+        assert_eq!(router.route_path("/missing/").1, None);
+    }
+
+    #[test]
+    fn app_router_should_follow_rewrites() {
+        let config = r#"
+name: test
+routes:
+  /old/:id:
+    - method: GET
+      rewrite: /users/:id?from=old
+  /users/:id:
+    - method: GET
+      handler: user
+  /loop:
+    - method: GET
+      rewrite: /loop
+  /moved/*rest:
+    - method: GET
+      redirect:
+        to: /new/*rest
+        status: 308
+"#;
+        let config: ProjectConfig = serde_yaml::from_str(config).unwrap();
+        let router = SwappableAppRouter::try_new("", config).unwrap();
+        let app_router = router.load();
+        let target = app_router.rewrite(&Method::GET, "/old/42").unwrap();
+        assert_eq!(target.as_deref(), Some("/users/42?from=old"));
+        assert_eq!(app_router.rewrite(&Method::GET, "/users/42").unwrap(), None);
+        assert!(matches!(
+            app_router.rewrite(&Method::GET, "/loop"),
+            Err(AppError::TooManyRewrites(_))
+        ));
+
+        let m = app_router.match_it(Method::GET, "/moved/a/b").unwrap();
+        let RouteAction::Redirect(redirect) = &m.value.action else {
+            panic!("should be a redirect");
+        };
+        let res = redirect.res(&match_params(&m));
+        assert_eq!(res.status, 308);
+        assert_eq!(res.headers["location"], "/new/a/b");
+
+        let config =
+            "name: test\nroutes:\n  /a:\n    - method: GET\n      handler: a\n      rewrite: /b\n";
+        let config: ProjectConfig = serde_yaml::from_str(config).unwrap();
+        assert!(SwappableAppRouter::try_new("", config).is_err());
+    }
+
     #[test]
     fn app_router_swap_should_work() {
         let config = include_str!("../fixtures/config.yml");
         let config: ProjectConfig = serde_yaml::from_str(config).unwrap();
-        let router = SwappableAppRouter::try_new("", config.routes).unwrap();
+        let router = SwappableAppRouter::try_new("", config).unwrap();
         let app_router = router.load();
         let m = app_router.match_it(Method::GET, "/api/hello/1").unwrap();
-        assert_eq!(m.value, "hello");
+        assert_eq!(m.value.handler(), Some("hello"));
 
         let new_config = include_str!("../fixtures/config1.yml");
         let new_config: ProjectConfig = serde_yaml::from_str(new_config).unwrap();
-        router.swap("", new_config.routes).unwrap();
+        router.swap("", new_config).unwrap();
         let app_router = router.load();
         let m = app_router.match_it(Method::GET, "/api/hello/1").unwrap();
-        assert_eq!(m.value, "hello1");
+        assert_eq!(m.value.handler(), Some("hello1"));
 
         let m = app_router.match_it(Method::POST, "/api/goodbye/2").unwrap();
-        assert_eq!(m.value, "handler2");
+        assert_eq!(m.value.handler(), Some("handler2"));
+    }
+
+    #[test]
+    fn app_router_rollback_should_work() {
+        let config = include_str!("../fixtures/config.yml");
+        let router =
+            SwappableAppRouter::try_new("v1", serde_yaml::from_str(config).unwrap()).unwrap();
+        assert!(router.rollback(None).is_err());
+        for code in ["v2", "v3"] {
+            router
+                .swap(code, serde_yaml::from_str(config).unwrap())
+                .unwrap();
+        }
+
+        let v1 = router.versions().last().unwrap().hash.clone();
+        assert_eq!(router.rollback(None).unwrap().source, "swap");
+        assert_eq!(router.load().code, "v2");
+        assert_eq!(router.rollback(Some(&v1)).unwrap().source, "initial");
+        assert_eq!(router.load().code, "v1");
+        let current: Vec<_> = router.versions().iter().map(|v| v.current).collect();
+        assert_eq!(current, [false, false, true]);
+        assert!(matches!(
+            router.rollback(Some("unknown")),
+            Err(AppError::VersionNotFound(_))
+        ));
+
+        for _ in 0..MAX_VERSIONS {
+            router
+                .swap("v4", serde_yaml::from_str(config).unwrap())
+                .unwrap();
+        }
+        assert_eq!(router.versions().len(), MAX_VERSIONS);
+    }
+
+    #[test]
+    fn app_router_hash_should_cover_the_config() {
+        let config = |route: &str| -> ProjectConfig {
+            let config =
+                format!("name: test\nroutes:\n  {route}:\n    - method: GET\n      handler: a\n");
+            serde_yaml::from_str(&config).unwrap()
+        };
+        let hash = |route: &str| AppRouterInner::try_new("code", config(route)).unwrap().hash;
+        assert_eq!(hash("/a"), hash("/a"));
+        assert_ne!(hash("/a"), hash("/b"));
     }
 }
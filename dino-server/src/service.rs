@@ -0,0 +1,415 @@
+use crate::{
+    get_router_by_host,
+    middleware::{credentials, request_id, REQUEST_ID_HEADER},
+    request_params, AppError, JsWorker, QueryString, RawBody, Req, Res, RouteAction, TenantRouters,
+};
+use axum::http::{
+    header::{AUTHORIZATION, CONTENT_TYPE, WWW_AUTHENTICATE},
+    HeaderMap, HeaderName, HeaderValue, Method, Request, StatusCode, Uri,
+};
+use dino_macros::FromJs;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+// a service calling another service is allowed, but not forever
+const MAX_SERVICE_DEPTH: usize = 8;
+
+/// Request passed to `env.SERVICE.fetch()`, normalized by the prelude.
+#[derive(Debug, FromJs)]
+pub struct ServiceReq {
+    pub method: String,
+    pub url: String,
+    pub headers: HashMap<String, String>,
+    pub body: Option<String>,
+}
+
+/// Dispatches service binding calls directly to the target tenant, in-process.
+/// The target route's auth, limits and timeout apply just like they do for
+/// external requests.
+#[derive(Clone)]
+pub struct ServiceContext {
+    routers: TenantRouters,
+    depth: usize,
+    // id of the calling request, used unless the js forwards one
+    request_id: Option<String>,
+    // when the calling request times out, nested calls can't outlive it
+    deadline: Option<Instant>,
+    // interrupts the workers once the server is done draining
+    drain: CancellationToken,
+}
+
+impl ServiceContext {
+    pub fn new(routers: TenantRouters) -> Self {
+        Self {
+            routers,
+            depth: 0,
+            request_id: None,
+            deadline: None,
+            drain: CancellationToken::new(),
+        }
+    }
+
+    /// Calls made with this context inherit the id of the calling request.
+    pub fn with_request_id(mut self, id: impl Into<String>) -> Self {
+        self.request_id = Some(id.into());
+        self
+    }
+
+    /// Calls made with this context are interrupted once the calling request
+    /// times out, or once the server is done draining.
+    pub fn with_deadline(mut self, timeout: Option<Duration>, drain: CancellationToken) -> Self {
+        self.deadline = timeout.map(|v| Instant::now() + v);
+        self.drain = drain;
+        self
+    }
+
+    /// Cancelled once the server is done draining.
+    pub(crate) fn drain(&self) -> CancellationToken {
+        self.drain.clone()
+    }
+
+    /// Call the tenant registered as `host`. Errors are turned into responses
+    /// just like they would be if the call went over the network.
+    pub fn fetch(&self, host: &str, req: ServiceReq) -> Res {
+        match self.try_fetch(host, req) {
+            Ok(res) => res,
+            Err(e) => {
+                warn!("service call to {} failed: {}", host, e);
+                Res {
+                    status: e.status().as_u16(),
+                    headers: HashMap::new(),
+                    body: Some(e.to_string()),
+                }
+            }
+        }
+    }
+
+    fn try_fetch(&self, host: &str, req: ServiceReq) -> Result<Res, AppError> {
+        if self.depth >= MAX_SERVICE_DEPTH {
+            return Err(AppError::ServiceDepthExceeded(host.to_string()));
+        }
+
+        let uri: Uri = req
+            .url
+            .parse()
+            .map_err(|_| AppError::InvalidServiceRequest(req.url.clone()))?;
+        // bindings to a mounted tenant carry its base path, e.g. `example.com/billing`
+        let (host, base) = host.split_at(host.find('/').unwrap_or(host.len()));
+        let path = uri.path_and_query().map_or("/", |v| v.as_str());
+        let uri: Uri = format!("{base}{path}")
+            .parse()
+            .map_err(|_| AppError::InvalidServiceRequest(req.url.clone()))?;
+        let tenant = get_router_by_host(host.to_string(), uri.path(), &self.routers)?;
+        let uri = tenant.unmount(&uri)?;
+        // service calls always go to the current version, canaries serve external traffic
+        let router = tenant.router.load();
+        let method: Method = req
+            .method
+            .to_uppercase()
+            .parse()
+            .map_err(|_| AppError::InvalidServiceRequest(req.method.clone()))?;
+
+        // rewrites are followed just like for external requests
+        let (route, _) = router.route_path(uri.path());
+        let (uri, route): (Uri, _) = match router.rewrite(&method, &route)? {
+            Some(target) => {
+                let uri: Uri = target
+                    .parse()
+                    .map_err(|_| AppError::InvalidRewrite(target))?;
+                let route = router.route_path(uri.path()).0;
+                (uri, route)
+            }
+            None => (uri, route),
+        };
+        let matched = router.match_it(method.clone(), &route)?;
+        let params = request_params(&matched, uri.path());
+
+        // the route is protected the same way as for external requests, cors
+        // and caching only matter over the network
+        let options = &matched.value.options;
+        let headers: HeaderMap = req
+            .headers
+            .iter()
+            .filter_map(|(k, v)| {
+                let k = HeaderName::from_bytes(k.as_bytes()).ok()?;
+                Some((k, HeaderValue::from_str(v).ok()?))
+            })
+            .collect();
+        let limits = matched.value.limits.or(&router.limits);
+        let mut parts = Request::new(()).into_parts().0;
+        parts.uri = uri.clone();
+        parts.headers = headers;
+        limits.check(&parts)?;
+        let content_type = parts
+            .headers
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok());
+        let limit = limits
+            .body
+            .unwrap_or_else(|| router.body_limits.limit_for(content_type));
+        if req.body.as_ref().is_some_and(|v| v.len() > limit) {
+            return Err(AppError::PayloadTooLarge(limit));
+        }
+        if let Some(scheme) = &options.auth {
+            let authorized = parts
+                .headers
+                .get(AUTHORIZATION)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| credentials(v, scheme))
+                .is_some_and(|v| !v.is_empty());
+            if !authorized {
+                return Ok(unauthorized(scheme));
+            }
+        }
+        let timeout = options.timeout.map(|v| Instant::now() + v);
+        let deadline = match (self.deadline, timeout) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+
+        let handler = match &matched.value.action {
+            RouteAction::Handler(v) => v,
+            RouteAction::Redirect(v) => return Ok(v.res(&params)),
+            RouteAction::Respond(v) => return Ok(v.res()),
+            // forwarding needs the async runtime, service calls are sync
+            RouteAction::Proxy(_) => {
+                let msg = format!("proxy route {} can't be called by a service", uri.path());
+                return Err(AppError::InvalidServiceRequest(msg));
+            }
+            RouteAction::Rewrite(_) => {
+                return Err(AppError::TooManyRewrites(uri.path().to_string()))
+            }
+        };
+        let query = QueryString::parse(uri.query());
+        if let Some(validator) = &matched.value.validator {
+            let body = req.body.as_deref().unwrap_or_default();
+            validator.validate(&params, &query, body.as_bytes())?;
+        }
+        let raw_body = req.body.clone().map(|v| RawBody(v.into()));
+        // an id forwarded by the js wins over the one of the calling request
+        let mut headers = req.headers;
+        let incoming = headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(REQUEST_ID_HEADER))
+            .map(|(_, v)| v.as_str())
+            .or(self.request_id.as_deref());
+        let request_id = request_id(incoming);
+        headers.retain(|k, _| !k.eq_ignore_ascii_case(REQUEST_ID_HEADER));
+        headers.insert(REQUEST_ID_HEADER.to_string(), request_id.clone());
+
+        let req = Req::builder()
+            .method(method.to_string())
+            .url(uri.to_string())
+            .query(query.last())
+            .query_all(query.all)
+            .raw_query(query.raw)
+            .params(params)
+            .headers(headers)
+            .body(req.body)
+            .raw_body(raw_body)
+            .subdomain(tenant.subdomain)
+            .request_id(request_id.clone())
+            .build();
+
+        let remaining = deadline.map(|v| v.saturating_duration_since(Instant::now()));
+        if remaining.is_some_and(|v| v.is_zero()) {
+            return Err(AppError::HandlerTimeout);
+        }
+        let worker = JsWorker::try_new_with_interrupt(&router.code, remaining, self.drain.clone())?;
+        worker.bind_services(&router.services, self.nested(&request_id, deadline))?;
+        let res = worker.run(handler, req).map_err(|e| {
+            if deadline.is_some_and(|v| Instant::now() >= v) {
+                AppError::HandlerTimeout
+            } else {
+                e.into()
+            }
+        })?;
+        // there is no response to send first, `waitUntil` work runs right away
+        if let Err(e) = worker.wait_until() {
+            warn!("waitUntil of {} failed: {:#}", handler, e);
+        }
+        Ok(res)
+    }
+
+    fn nested(&self, request_id: &str, deadline: Option<Instant>) -> Self {
+        Self {
+            routers: self.routers.clone(),
+            depth: self.depth + 1,
+            request_id: Some(request_id.to_string()),
+            deadline,
+            drain: self.drain.clone(),
+        }
+    }
+}
+
+fn unauthorized(scheme: &str) -> Res {
+    Res {
+        status: StatusCode::UNAUTHORIZED.as_u16(),
+        headers: HashMap::from([(WWW_AUTHENTICATE.to_string(), scheme.to_string())]),
+        body: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ProjectConfig, SwappableAppRouter};
+
+    const CONFIG: &str = r#"
+name: test
+services:
+  USERS: users.local
+routes:
+  /api/users/:id:
+    - method: GET
+      handler: hello
+"#;
+
+    #[test]
+    fn service_binding_should_dispatch_in_process() {
+        let users = r#"
+    (function(){
+        async function hello(req){
+            return { status: 200, headers: {}, body: `user ${req.params.id}` };
+        }
+        return{hello:hello};
+    })();
+    "#;
+        let gateway = r#"
+    (function(){
+        async function hello(req, env){
+            const res = await env.USERS.fetch(`/api/users/${req.params.id}`);
+            return { status: res.status, headers: {}, body: await res.text() };
+        }
+        return{hello:hello};
+    })();
+    "#;
+        let config: ProjectConfig = serde_yaml::from_str(CONFIG).unwrap();
+        let routers = TenantRouters::default();
+        routers.insert(
+            "users.local".to_string(),
+            SwappableAppRouter::try_new(users, config).unwrap(),
+        );
+        let config: ProjectConfig = serde_yaml::from_str(CONFIG).unwrap();
+        routers.insert(
+            "gateway.local".to_string(),
+            SwappableAppRouter::try_new(gateway, config).unwrap(),
+        );
+
+        let ctx = ServiceContext::new(routers);
+        let req = ServiceReq {
+            method: "GET".to_string(),
+            url: "/api/users/42".to_string(),
+            headers: HashMap::new(),
+            body: None,
+        };
+        let res = ctx.fetch("gateway.local", req);
+        assert_eq!(res.status, 200);
+        assert_eq!(res.body.as_deref(), Some("user 42"));
+
+        let req = ServiceReq {
+            method: "GET".to_string(),
+            url: "/api/users/42".to_string(),
+            headers: HashMap::new(),
+            body: None,
+        };
+        let res = ctx.fetch("unknown.local", req);
+        assert_eq!(res.status, 404);
+    }
+
+    #[test]
+    fn service_binding_should_inherit_request_id() {
+        let users = r#"
+    (function(){
+        async function hello(req){
+            return { status: 200, headers: {}, body: req.requestId };
+        }
+        return{hello:hello};
+    })();
+    "#;
+        let gateway = r#"
+    (function(){
+        async function hello(req, env){
+            const res = await env.USERS.fetch(`/api/users/${req.params.id}`);
+            return { status: res.status, headers: {}, body: await res.text() };
+        }
+        return{hello:hello};
+    })();
+    "#;
+        let routers = TenantRouters::default();
+        for (host, code) in [("users.local", users), ("gateway.local", gateway)] {
+            let config: ProjectConfig = serde_yaml::from_str(CONFIG).unwrap();
+            let router = SwappableAppRouter::try_new(code, config).unwrap();
+            routers.insert(host.to_string(), router);
+        }
+
+        let ctx = ServiceContext::new(routers).with_request_id("abc-123");
+        let req = ServiceReq {
+            method: "GET".to_string(),
+            url: "/api/users/42".to_string(),
+            headers: HashMap::new(),
+            body: None,
+        };
+        let res = ctx.fetch("gateway.local", req);
+        assert_eq!(res.body.as_deref(), Some("abc-123"));
+    }
+
+    #[test]
+    fn service_binding_should_enforce_route_options() {
+        let config = r#"
+name: test
+routes:
+  /private:
+    - method: GET
+      handler: hello
+      auth: bearer
+  /slow:
+    - method: GET
+      handler: slow
+      timeout: 50ms
+"#;
+        let code = r#"
+    (function(){
+        async function hello(req){
+            return { status: 200, headers: {}, body: "ok" };
+        }
+        async function slow(req){
+            while (true) {}
+        }
+        return{hello:hello,slow:slow};
+    })();
+    "#;
+        let config: ProjectConfig = serde_yaml::from_str(config).unwrap();
+        let routers = TenantRouters::default();
+        routers.insert(
+            "users.local".to_string(),
+            SwappableAppRouter::try_new(code, config).unwrap(),
+        );
+        let ctx = ServiceContext::new(routers);
+        let req = |url: &str, headers: &[(&str, &str)]| ServiceReq {
+            method: "GET".to_string(),
+            url: url.to_string(),
+            headers: headers
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            body: None,
+        };
+
+        let res = ctx.fetch("users.local", req("/private", &[]));
+        assert_eq!(res.status, 401);
+        assert_eq!(res.headers["www-authenticate"], "bearer");
+        let res = ctx.fetch(
+            "users.local",
+            req("/private", &[("authorization", "Bearer abc")]),
+        );
+        assert_eq!(res.status, 200);
+
+        let res = ctx.fetch("users.local", req("/slow", &[]));
+        assert_eq!(res.status, 504);
+    }
+}
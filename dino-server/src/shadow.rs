@@ -0,0 +1,291 @@
+use crate::{request_params, AppError, AppRouter, JsWorker, Req, Res, RouteAction, ServiceContext};
+use anyhow::anyhow;
+use axum::http::{Method, Uri};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::task::spawn_blocking;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+/// Which requests are mirrored to the shadow version.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShadowRule {
+    // share of the requests mirrored, 0 - 100
+    #[serde(default = "default_percent")]
+    pub percent: u8,
+    // also mirror methods other than GET / HEAD, only safe if their handlers
+    // have no side effects
+    #[serde(default)]
+    pub unsafe_methods: bool,
+}
+
+/// A version that gets a copy of live requests, its responses are only compared.
+pub(crate) struct Shadow {
+    pub(crate) router: AppRouter,
+    pub(crate) rule: ShadowRule,
+    mirrored: AtomicU64,
+    status_diffs: AtomicU64,
+    body_diffs: AtomicU64,
+    errors: AtomicU64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShadowStatus {
+    // build hash of the shadow
+    pub hash: String,
+    pub rule: ShadowRule,
+    pub mirrored: u64,
+    pub status_diffs: u64,
+    pub body_diffs: u64,
+    // shadow handlers that failed to run
+    pub errors: u64,
+}
+
+fn default_percent() -> u8 {
+    100
+}
+
+impl Default for ShadowRule {
+    fn default() -> Self {
+        Self {
+            percent: default_percent(),
+            unsafe_methods: false,
+        }
+    }
+}
+
+impl ShadowRule {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.percent > 100 {
+            return Err(format!(
+                "shadow percent must be 0 - 100, got {}",
+                self.percent
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Shadow {
+    pub(crate) fn new(router: AppRouter, rule: ShadowRule) -> Self {
+        Self {
+            router,
+            rule,
+            mirrored: AtomicU64::new(0),
+            status_diffs: AtomicU64::new(0),
+            body_diffs: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn sample(&self, method: &Method) -> bool {
+        let safe = matches!(*method, Method::GET | Method::HEAD);
+        (safe || self.rule.unsafe_methods)
+            && rand::thread_rng().gen_range(0..100) < self.rule.percent
+    }
+
+    pub(crate) fn status(&self) -> ShadowStatus {
+        ShadowStatus {
+            hash: self.router.hash.clone(),
+            rule: self.rule.clone(),
+            mirrored: self.mirrored.load(Ordering::Relaxed),
+            status_diffs: self.status_diffs.load(Ordering::Relaxed),
+            body_diffs: self.body_diffs.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Run `req` on the shadow version and compare its response with the live
+    /// one. Runs in the background, the client never waits for it.
+    pub(crate) async fn mirror(
+        self: Arc<Self>,
+        req: Req,
+        live: (u16, Option<String>),
+        timeout: Option<Duration>,
+        deadline: CancellationToken,
+    ) {
+        let target = format!("{} {}", req.method, req.url);
+        let shadow = match self.run(req, timeout, deadline).await {
+            Ok(Some(res)) => (res.status, res.body),
+            // proxies are never mirrored, they would hit the upstream twice
+            Ok(None) => return,
+            Err(e) => {
+                self.errors.fetch_add(1, Ordering::Relaxed);
+                warn!("shadow {} failed on {}: {}", self.router.hash, target, e);
+                (e.status().as_u16(), None)
+            }
+        };
+        self.mirrored.fetch_add(1, Ordering::Relaxed);
+
+        if live.0 != shadow.0 {
+            self.status_diffs.fetch_add(1, Ordering::Relaxed);
+            warn!(
+                "shadow {} differs on {}: status {} vs {}",
+                self.router.hash, target, live.0, shadow.0
+            );
+        } else if live.1 != shadow.1 {
+            self.body_diffs.fetch_add(1, Ordering::Relaxed);
+            let len = |v: &Option<String>| v.as_ref().map_or(0, |v| v.len());
+            warn!(
+                "shadow {} differs on {}: body of {} bytes vs {} bytes",
+                self.router.hash,
+                target,
+                len(&live.1),
+                len(&shadow.1)
+            );
+        } else {
+            info!("shadow {} matches on {}", self.router.hash, target);
+        }
+    }
+
+    async fn run(
+        &self,
+        mut req: Req,
+        timeout: Option<Duration>,
+        deadline: CancellationToken,
+    ) -> Result<Option<Res>, AppError> {
+        let method: Method = req.method.parse().map_err(|e| anyhow!("{e}"))?;
+        let uri: Uri = req.url.parse().map_err(|e| anyhow!("{e}"))?;
+        // the shadow may route the path differently
+        let (route, _) = self.router.route_path(uri.path());
+        let (handler, params) = match self.router.match_it(method, &route) {
+            Ok(m) => match &m.value.action {
+                RouteAction::Handler(v) => (v.clone(), request_params(&m, uri.path())),
+                RouteAction::Redirect(v) => {
+                    return Ok(Some(v.res(&request_params(&m, uri.path()))))
+                }
+                RouteAction::Respond(v) => return Ok(Some(v.res())),
+                RouteAction::Proxy(_) | RouteAction::Rewrite(_) => return Ok(None),
+            },
+            Err(e) => return Ok(Some(error_res(e))),
+        };
+        req.params = params;
+
+        let router = self.router.clone();
+        let res = spawn_blocking(move || {
+            let worker = JsWorker::try_new(&router.code)?;
+            worker.set_interrupt(timeout, deadline);
+            // side effects must not happen twice: bindings exist but reach no
+            // tenant, and `waitUntil` work is skipped
+            let stub = ServiceContext::new(Default::default());
+            worker.bind_services(&router.services, stub)?;
+            worker.run(&handler, req)
+        })
+        .await
+        .map_err(|e| anyhow!(e))??;
+        Ok(Some(res))
+    }
+}
+
+// the response the client would get for a routing error
+fn error_res(e: AppError) -> Res {
+    Res {
+        status: e.status().as_u16(),
+        headers: Default::default(),
+        body: Some(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ProjectConfig, SwappableAppRouter};
+
+    const CONFIG: &str = r#"
+name: test
+routes:
+  /api/users/:id:
+    - method: GET
+      handler: hello
+"#;
+
+    fn shadow() -> Arc<Shadow> {
+        let code = r#"
+    (function(){
+        async function hello(req){
+            const status = req.params.id === "0" ? 404 : 200;
+            return { status, headers: {}, body: `user ${req.params.id}` };
+        }
+        return{hello:hello};
+    })();
+    "#;
+        let config: ProjectConfig = serde_yaml::from_str(CONFIG).unwrap();
+        let router = SwappableAppRouter::try_new(code, config).unwrap().load();
+        Arc::new(Shadow::new(router, ShadowRule::default()))
+    }
+
+    fn req(path: &str) -> Req {
+        Req::builder().method("GET").url(path).build()
+    }
+
+    #[tokio::test]
+    async fn shadow_should_report_diffs() {
+        let shadow = shadow();
+        let same = (200, Some("user 1".to_string()));
+        let other = (200, Some("user: 1".to_string()));
+        for (path, live) in [("/api/users/1", same.clone()), ("/api/users/1", other)] {
+            shadow
+                .clone()
+                .mirror(req(path), live, None, Default::default())
+                .await;
+        }
+        shadow
+            .clone()
+            .mirror(req("/api/users/0"), same.clone(), None, Default::default())
+            .await;
+        shadow
+            .clone()
+            .mirror(req("/missing"), same, None, Default::default())
+            .await;
+
+        let status = shadow.status();
+        assert_eq!(status.mirrored, 4);
+        assert_eq!((status.status_diffs, status.body_diffs), (2, 1));
+        assert_eq!(status.errors, 0);
+    }
+
+    #[test]
+    fn shadow_should_only_sample_safe_methods_by_default() {
+        let shadow = shadow();
+        assert!(shadow.sample(&Method::GET));
+        assert!(shadow.sample(&Method::HEAD));
+        assert!(!shadow.sample(&Method::POST));
+        assert!(!shadow.sample(&Method::DELETE));
+
+        let rule = ShadowRule {
+            unsafe_methods: true,
+            ..Default::default()
+        };
+        let shadow = Shadow::new(shadow.router.clone(), rule);
+        assert!(shadow.sample(&Method::POST));
+    }
+
+    #[tokio::test]
+    async fn shadow_should_not_reach_live_services() {
+        let code = r#"
+    (function(){
+        async function hello(req, env){
+            const res = await env.users.fetch("/");
+            return { status: 200, headers: {}, body: `${res.status}` };
+        }
+        return{hello:hello};
+    })();
+    "#;
+        let config = format!("{CONFIG}services:\n  users: users.com\n");
+        let config: ProjectConfig = serde_yaml::from_str(&config).unwrap();
+        let router = SwappableAppRouter::try_new(code, config).unwrap().load();
+        let shadow = Shadow::new(router, ShadowRule::default());
+        let res = shadow
+            .run(req("/api/users/1"), None, Default::default())
+            .await
+            .unwrap();
+        assert_eq!(res.unwrap().body.as_deref(), Some("404"));
+    }
+}
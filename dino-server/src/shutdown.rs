@@ -0,0 +1,132 @@
+use anyhow::Result;
+use std::{future::Future, time::Duration};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tracing::{info, warn};
+
+/// Stops a running server: listeners stop accepting, then in-flight requests
+/// and `waitUntil` tasks get up to the drain timeout to finish.
+#[derive(Debug, Clone, Default)]
+pub struct ShutdownHandle {
+    token: CancellationToken,
+    // cancelled once the drain timeout passes, js still running is interrupted
+    deadline: CancellationToken,
+}
+
+impl ShutdownHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn shutdown(&self) {
+        self.token.cancel();
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    /// Resolves once the server is asked to shut down.
+    pub async fn wait(&self) {
+        self.token.cancelled().await
+    }
+
+    // js workers check it to give up once draining is over
+    pub(crate) fn deadline(&self) -> CancellationToken {
+        self.deadline.clone()
+    }
+}
+
+/// Shut down on SIGINT or SIGTERM.
+pub(crate) async fn watch_signals(handle: ShutdownHandle) {
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut v) => v.recv().await,
+            Err(e) => {
+                warn!("failed to listen for SIGTERM: {}", e);
+                std::future::pending().await
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<Option<()>>();
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => info!("received SIGINT"),
+        _ = terminate => info!("received SIGTERM"),
+        // shut down some other way, nothing left to do
+        _ = handle.wait() => return,
+    }
+    handle.shutdown();
+}
+
+/// Wait for the servers to drain and the background tasks to finish, for up
+/// to `timeout`. Whatever is still running afterwards is cut off, js workers
+/// included, or the runtime would wait for them when it's dropped.
+pub(crate) async fn drain(
+    servers: impl Future<Output = Result<()>>,
+    tasks: &TaskTracker,
+    timeout: Duration,
+    handle: &ShutdownHandle,
+) -> Result<()> {
+    info!(
+        "shutting down, draining for up to {}",
+        humantime::format_duration(timeout)
+    );
+    tasks.close();
+    let drained = async {
+        servers.await?;
+        tasks.wait().await;
+        Ok(())
+    };
+    match tokio::time::timeout(timeout, drained).await {
+        Ok(ret) => ret,
+        Err(_) => {
+            warn!("drain timed out, {} background tasks cut off", tasks.len());
+            handle.deadline.cancel();
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{start_server, ServerConfig};
+
+    #[tokio::test]
+    async fn drain_should_wait_for_tasks_until_timeout() {
+        let handle = ShutdownHandle::new();
+        let tasks = TaskTracker::new();
+        tasks.spawn(tokio::time::sleep(Duration::from_millis(20)));
+        drain(async { Ok(()) }, &tasks, Duration::from_secs(5), &handle)
+            .await
+            .unwrap();
+        assert!(tasks.is_empty());
+        assert!(!handle.deadline().is_cancelled());
+
+        let tasks = TaskTracker::new();
+        tasks.spawn(tokio::time::sleep(Duration::from_secs(60)));
+        drain(async { Ok(()) }, &tasks, Duration::from_millis(20), &handle)
+            .await
+            .unwrap();
+        assert_eq!(tasks.len(), 1);
+        assert!(handle.deadline().is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn start_server_should_stop_on_shutdown() {
+        let handle = ShutdownHandle::new();
+        let config = ServerConfig::builder()
+            .port(0)
+            .shutdown(handle.clone())
+            .drain_timeout(Duration::from_secs(1))
+            .build();
+        let server = tokio::spawn(start_server(config, vec![]));
+        handle.shutdown();
+        let ret = tokio::time::timeout(Duration::from_secs(5), server).await;
+        assert!(ret.unwrap().unwrap().is_ok());
+        assert!(handle.is_shutdown());
+    }
+}
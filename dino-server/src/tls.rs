@@ -0,0 +1,338 @@
+use crate::{
+    host::{match_pattern, strip_port},
+    ListenAddr,
+};
+use anyhow::{anyhow, Context, Result};
+use arc_swap::ArcSwap;
+use axum::{
+    extract::{Host, Request},
+    response::{IntoResponse, Redirect},
+    Router,
+};
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::BufReader,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+use tokio_rustls::{
+    rustls::{
+        crypto::ring,
+        server::{ClientHello, ResolvesServerCert},
+        sign::CertifiedKey,
+        ServerConfig,
+    },
+    TlsAcceptor,
+};
+use tracing::{info, warn};
+use typed_builder::TypedBuilder;
+
+/// Serve https next to plain http.
+#[derive(Debug, Clone, TypedBuilder)]
+pub struct TlsConfig {
+    // port of the https urls plain http is redirected to
+    pub port: u16,
+    // `0.0.0.0:{port}` if empty
+    #[builder(default)]
+    pub listen: Vec<ListenAddr>,
+    // used when no tenant certificate matches the SNI name
+    #[builder(default)]
+    pub default_cert: Option<CertFiles>,
+    // answer plain http with a redirect to https instead of serving it
+    #[builder(default)]
+    pub redirect_http: bool,
+    // how often certificate files are checked for changes
+    #[builder(default = Duration::from_secs(30))]
+    pub reload_interval: Duration,
+}
+
+/// PEM encoded certificate chain and private key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CertFiles {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+/// Picks the certificate of a connection by SNI: exact hosts first, then the
+/// longest matching pattern, then the default certificate.
+#[derive(Debug)]
+pub(crate) struct CertResolver {
+    // lowercase host or pattern => certificate
+    certs: HashMap<String, Arc<Cert>>,
+    default: Option<Arc<Cert>>,
+}
+
+#[derive(Debug)]
+struct Cert {
+    files: CertFiles,
+    key: ArcSwap<CertifiedKey>,
+    // newest modification time of the files when they were loaded
+    modified: Mutex<Option<SystemTime>>,
+}
+
+impl CertFiles {
+    pub fn new(cert: impl Into<PathBuf>, key: impl Into<PathBuf>) -> Self {
+        Self {
+            cert: cert.into(),
+            key: key.into(),
+        }
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        let cert = fs::metadata(&self.cert).and_then(|v| v.modified()).ok()?;
+        let key = fs::metadata(&self.key).and_then(|v| v.modified()).ok()?;
+        Some(cert.max(key))
+    }
+
+    fn load(&self) -> Result<CertifiedKey> {
+        let open = |path: &PathBuf| {
+            File::open(path)
+                .map(BufReader::new)
+                .with_context(|| format!("failed to open {}", path.display()))
+        };
+        let certs = rustls_pemfile::certs(&mut open(&self.cert)?).collect::<Result<Vec<_>, _>>()?;
+        if certs.is_empty() {
+            return Err(anyhow!("no certificate in {}", self.cert.display()));
+        }
+        let key = rustls_pemfile::private_key(&mut open(&self.key)?)?
+            .ok_or_else(|| anyhow!("no private key in {}", self.key.display()))?;
+        let key = ring::sign::any_supported_type(&key)?;
+        Ok(CertifiedKey::new(certs, key))
+    }
+}
+
+impl Cert {
+    fn load(files: CertFiles) -> Result<Self> {
+        let modified = files.modified();
+        let key = files.load()?;
+        Ok(Self {
+            files,
+            key: ArcSwap::from_pointee(key),
+            modified: Mutex::new(modified),
+        })
+    }
+
+    // a broken file keeps the previous certificate in use
+    fn reload(&self) -> bool {
+        let modified = self.files.modified();
+        let mut current = self.modified.lock().unwrap();
+        if modified.is_none() || modified == *current {
+            return false;
+        }
+        match self.files.load() {
+            Ok(key) => {
+                self.key.store(Arc::new(key));
+                *current = modified;
+                info!("reloaded certificate {}", self.files.cert.display());
+                true
+            }
+            Err(e) => {
+                warn!("failed to reload {}: {:#}", self.files.cert.display(), e);
+                false
+            }
+        }
+    }
+}
+
+impl CertResolver {
+    /// Every certificate is loaded upfront, a bad one fails the start.
+    pub(crate) fn try_new(
+        certs: Vec<(String, CertFiles)>,
+        default: Option<CertFiles>,
+    ) -> Result<Self> {
+        // hosts sharing files share the certificate
+        let mut loaded: Vec<Arc<Cert>> = vec![];
+        let mut load = |files: CertFiles| -> Result<Arc<Cert>> {
+            if let Some(cert) = loaded.iter().find(|v| v.files == files) {
+                return Ok(cert.clone());
+            }
+            let cert = Arc::new(Cert::load(files)?);
+            loaded.push(cert.clone());
+            Ok(cert)
+        };
+        let default = default.map(&mut load).transpose()?;
+        let certs = certs
+            .into_iter()
+            .map(|(host, files)| Ok((host.to_lowercase(), load(files)?)))
+            .collect::<Result<_>>()?;
+        Ok(Self { certs, default })
+    }
+
+    /// Reload the certificates whose files changed, returns how many were.
+    pub(crate) fn reload(&self) -> usize {
+        let mut certs: Vec<&Arc<Cert>> = self.certs.values().chain(&self.default).collect();
+        certs.sort_by_key(|v| Arc::as_ptr(v));
+        certs.dedup_by(|a, b| Arc::ptr_eq(a, b));
+        certs.into_iter().filter(|v| v.reload()).count()
+    }
+
+    fn find(&self, name: Option<&str>) -> Option<Arc<CertifiedKey>> {
+        let cert = name.map(|v| v.to_lowercase()).and_then(|name| {
+            self.certs.get(&name).or_else(|| {
+                self.certs
+                    .iter()
+                    .filter(|(pattern, _)| match_pattern(pattern, &name).is_some())
+                    .max_by_key(|(pattern, _)| pattern.len())
+                    .map(|(_, cert)| cert)
+            })
+        });
+        cert.or(self.default.as_ref()).map(|v| v.key.load_full())
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        self.find(hello.server_name())
+    }
+}
+
+/// Accepts tls connections with the certificate picked by `resolver`,
+/// negotiating h2 or http/1.1.
+pub(crate) fn tls_acceptor(resolver: Arc<CertResolver>) -> Result<TlsAcceptor> {
+    let mut config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_cert_resolver(resolver);
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Check the certificate files for changes every `interval`.
+pub(crate) async fn watch_certs(resolver: Arc<CertResolver>, interval: Duration) {
+    let mut interval = tokio::time::interval(interval);
+    // the first tick completes immediately
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        resolver.reload();
+    }
+}
+
+/// Permanently redirect every plain http request to its https url.
+pub(crate) fn redirect_router(port: u16) -> Router {
+    Router::new().fallback(move |Host(host): Host, req: Request| async move {
+        let host = strip_port(&host);
+        let path = req.uri().path_and_query().map_or("/", |v| v.as_str());
+        let location = match port {
+            443 => format!("https://{host}{path}"),
+            port => format!("https://{host}:{port}{path}"),
+        };
+        Redirect::permanent(&location).into_response()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        listen::{serve, Listener},
+        ShutdownHandle,
+    };
+    use axum::{body::Body, http::StatusCode, routing::get};
+    use std::path::Path;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::{
+        rustls::{pki_types::ServerName, ClientConfig, RootCertStore},
+        TlsConnector,
+    };
+    use tower::ServiceExt;
+
+    // self-signed, returns the der of the certificate
+    fn write_cert(dir: &Path, name: &str) -> (CertFiles, Vec<u8>) {
+        let cert = rcgen::generate_simple_self_signed(vec![name.to_string()]).unwrap();
+        let file = name.replace('*', "_");
+        let files = CertFiles::new(
+            dir.join(format!("{file}.pem")),
+            dir.join(format!("{file}.key")),
+        );
+        fs::write(&files.cert, cert.cert.pem()).unwrap();
+        fs::write(&files.key, cert.key_pair.serialize_pem()).unwrap();
+        (files, cert.cert.der().to_vec())
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("dino-tls-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn der(key: Option<Arc<CertifiedKey>>) -> Vec<u8> {
+        key.unwrap().cert[0].to_vec()
+    }
+
+    #[test]
+    fn cert_resolver_should_pick_by_sni_and_reload() {
+        let dir = temp_dir("resolver");
+        let (a, a_der) = write_cert(&dir, "a.com");
+        let (b, b_der) = write_cert(&dir, "*.b.com");
+        let (default, default_der) = write_cert(&dir, "localhost");
+        let certs = vec![("A.com".to_string(), a.clone()), ("*.b.com".to_string(), b)];
+        let resolver = CertResolver::try_new(certs, Some(default)).unwrap();
+
+        assert_eq!(der(resolver.find(Some("a.com"))), a_der);
+        assert_eq!(der(resolver.find(Some("x.B.com"))), b_der);
+        assert_eq!(der(resolver.find(Some("b.com"))), default_der);
+        assert_eq!(der(resolver.find(None)), default_der);
+        assert_eq!(resolver.reload(), 0);
+
+        let (_, new_der) = write_cert(&dir, "a.com");
+        assert_eq!(resolver.reload(), 1);
+        assert_eq!(der(resolver.find(Some("a.com"))), new_der);
+        // broken files keep the previous certificate
+        fs::write(&a.key, "oops").unwrap();
+        assert_eq!(resolver.reload(), 0);
+        assert_eq!(der(resolver.find(Some("a.com"))), new_der);
+
+        let missing = CertFiles::new(dir.join("missing.pem"), dir.join("missing.key"));
+        assert!(CertResolver::try_new(vec![], Some(missing)).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn tls_acceptor_should_terminate_tls() {
+        let dir = temp_dir("serve");
+        let (files, der) = write_cert(&dir, "localhost");
+        let resolver = Arc::new(CertResolver::try_new(vec![], Some(files)).unwrap());
+        let acceptor = tls_acceptor(resolver).unwrap();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new().route("/", get(|| async { "hello" }));
+        let listener = Listener::Tcp(listener);
+        tokio::spawn(serve(listener, app, Some(acceptor), ShutdownHandle::new()));
+
+        let mut roots = RootCertStore::empty();
+        roots.add(der.into()).unwrap();
+        let config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let name = ServerName::try_from("localhost").unwrap();
+        let mut stream = TlsConnector::from(Arc::new(config))
+            .connect(name, stream)
+            .await
+            .unwrap();
+        let req = "GET / HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n";
+        stream.write_all(req.as_bytes()).await.unwrap();
+        let mut res = String::new();
+        stream.read_to_string(&mut res).await.ok();
+        assert!(res.starts_with("HTTP/1.1 200"));
+        assert!(res.ends_with("hello"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn redirect_router_should_redirect_to_https() {
+        let req = Request::builder()
+            .uri("/a?b=1")
+            .header("host", "example.com:8080")
+            .body(Body::empty())
+            .unwrap();
+        let res = redirect_router(8443).oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(res.headers()["location"], "https://example.com:8443/a?b=1");
+    }
+}
@@ -0,0 +1,457 @@
+use crate::{config::ACTION_KEYS, env_var, interpolate_str, ProjectConfig, RouteMethod};
+use serde_json::Value;
+use serde_path_to_error::Segment;
+use std::{collections::HashSet, fmt};
+use thiserror::Error;
+use yaml_rust2::{
+    parser::{Event, MarkedEventReceiver, Parser},
+    scanner::Marker,
+};
+
+/// A problem found in config.yml, with its (1-based) position.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigIssue {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+/// Every problem found in a config file, one `file:line:column: message` per line.
+#[derive(Debug, Error)]
+pub struct ConfigError {
+    pub file: String,
+    pub issues: Vec<ConfigIssue>,
+}
+
+// yaml tree that remembers where each node starts
+#[derive(Debug)]
+enum Node {
+    Map(Vec<(Node, Node)>, Marker),
+    Seq(Vec<Node>, Marker),
+    Scalar(String, Marker),
+}
+
+#[derive(Default)]
+struct TreeBuilder {
+    stack: Vec<Node>,
+    // pending key of each open mapping
+    keys: Vec<Option<Node>>,
+    root: Option<Node>,
+}
+
+impl ConfigIssue {
+    pub fn new(line: usize, column: usize, message: impl Into<String>) -> Self {
+        Self {
+            line,
+            column,
+            message: message.into(),
+        }
+    }
+
+    fn at(node: &Node, message: impl Into<String>) -> Self {
+        let mark = node.marker();
+        Self::new(mark.line(), mark.col() + 1, message)
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, issue) in self.issues.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(
+                f,
+                "{}:{}:{}: {}",
+                self.file, issue.line, issue.column, issue.message
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Node {
+    fn marker(&self) -> Marker {
+        match self {
+            // block mappings start at their first key, not at the `:` after it
+            Node::Map(entries, m) => entries.first().map_or(*m, |(k, _)| k.marker()),
+            Node::Seq(_, m) | Node::Scalar(_, m) => *m,
+        }
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Node::Scalar(v, _) => Some(v),
+            _ => None,
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<&Node> {
+        match self {
+            Node::Map(entries, _) => entries
+                .iter()
+                .find(|(k, _)| k.as_str() == Some(key))
+                .map(|(_, v)| v),
+            _ => None,
+        }
+    }
+}
+
+impl MarkedEventReceiver for TreeBuilder {
+    fn on_event(&mut self, ev: Event, mark: Marker) {
+        match ev {
+            Event::MappingStart(..) => {
+                self.stack.push(Node::Map(vec![], mark));
+                self.keys.push(None);
+            }
+            Event::SequenceStart(..) => self.stack.push(Node::Seq(vec![], mark)),
+            Event::MappingEnd => {
+                self.keys.pop();
+                if let Some(node) = self.stack.pop() {
+                    self.insert(node);
+                }
+            }
+            Event::SequenceEnd => {
+                if let Some(node) = self.stack.pop() {
+                    self.insert(node);
+                }
+            }
+            Event::Scalar(v, ..) => self.insert(Node::Scalar(v, mark)),
+            // aliases are not followed, serde still checks what they point to
+            Event::Alias(_) => self.insert(Node::Scalar(String::new(), mark)),
+            _ => {}
+        }
+    }
+}
+
+impl TreeBuilder {
+    fn insert(&mut self, node: Node) {
+        match self.stack.last_mut() {
+            Some(Node::Map(entries, _)) => {
+                let key = self.keys.last_mut().expect("every mapping has a key slot");
+                match key.take() {
+                    Some(k) => entries.push((k, node)),
+                    None => *key = Some(node),
+                }
+            }
+            Some(Node::Seq(items, _)) => items.push(node),
+            _ => {
+                if self.root.is_none() {
+                    self.root = Some(node);
+                }
+            }
+        }
+    }
+}
+
+/// Find the problems serde can't report, or only reports one at a time:
+/// syntax errors, unknown keys, duplicate paths and methods, conflicting
+/// path patterns, invalid handler names and undefined variables.
+pub fn validate_config(content: &str, profile: Option<&str>) -> Vec<ConfigIssue> {
+    let root = match parse_tree(content) {
+        Ok(Some(v)) => v,
+        Ok(None) => return vec![],
+        Err(issue) => return vec![issue],
+    };
+
+    let mut issues = vec![];
+    let schema = ProjectConfig::schema();
+    check_keys(&root, &schema, &schema["definitions"], &mut issues);
+    check_routes(&root, &mut issues);
+    check_vars(&root, true, &mut issues);
+    // other profiles may rely on variables that are not set here
+    if let Some(node) = profile.and_then(|name| root.get("profiles")?.get(name)) {
+        check_routes(node, &mut issues);
+        check_vars(node, false, &mut issues);
+    }
+    issues
+}
+
+/// Position of the node at `path` (as reported by serde), looked up in the
+/// selected profile first. Falls back to the closest parent that exists.
+pub(crate) fn locate_path(
+    content: &str,
+    path: &serde_path_to_error::Path,
+    profile: Option<&str>,
+) -> Option<(usize, usize)> {
+    let root = parse_tree(content).ok()??;
+    let mut found = lookup(&root, path);
+    // values of the profile override the base config
+    if let Some(overlay) = profile.and_then(|name| root.get("profiles")?.get(name)) {
+        let (depth, node) = lookup(overlay, path);
+        if depth > 0 && depth >= found.0 {
+            found = (depth, node);
+        }
+    }
+    let mark = found.1.marker();
+    Some((mark.line(), mark.col() + 1))
+}
+
+fn parse_tree(content: &str) -> Result<Option<Node>, ConfigIssue> {
+    let mut builder = TreeBuilder::default();
+    if let Err(e) = Parser::new_from_str(content).load(&mut builder, false) {
+        let mark = e.marker();
+        return Err(ConfigIssue::new(mark.line(), mark.col() + 1, e.info()));
+    }
+    Ok(builder.root)
+}
+
+// follow the path as far as possible, returns how deep it got
+fn lookup<'a>(node: &'a Node, path: &serde_path_to_error::Path) -> (usize, &'a Node) {
+    let mut current = node;
+    let mut depth = 0;
+    for segment in path.iter() {
+        let next = match (segment, current) {
+            (Segment::Map { key }, _) => current.get(key),
+            (Segment::Seq { index }, Node::Seq(items, _)) => items.get(*index),
+            _ => None,
+        };
+        match next {
+            Some(v) => {
+                current = v;
+                depth += 1;
+            }
+            None => break,
+        }
+    }
+    (depth, current)
+}
+
+// walk the tree along the JSON Schema and report keys it doesn't allow
+fn check_keys(node: &Node, schema: &Value, defs: &Value, issues: &mut Vec<ConfigIssue>) {
+    let schema = resolve(schema, defs);
+    match node {
+        Node::Map(entries, _) => {
+            let props = &schema["properties"];
+            let mut seen = HashSet::new();
+            for (k, v) in entries {
+                let Some(key) = k.as_str() else {
+                    continue;
+                };
+                if !seen.insert(key) {
+                    issues.push(ConfigIssue::at(k, format!("duplicate key `{key}`")));
+                    continue;
+                }
+                if let Some(s) = props.get(key) {
+                    check_keys(v, s, defs, issues);
+                } else if schema["additionalProperties"] == Value::Bool(false) {
+                    let expected = props
+                        .as_object()
+                        .map(|v| v.keys().cloned().collect::<Vec<_>>().join(", "))
+                        .unwrap_or_default();
+                    let msg = format!("unknown key `{key}`, expected one of: {expected}");
+                    issues.push(ConfigIssue::at(k, msg));
+                } else if let Some(s) = schema.get("additionalProperties") {
+                    check_keys(v, s, defs, issues);
+                }
+            }
+        }
+        Node::Seq(items, _) => {
+            if let Some(s) = schema.get("items") {
+                for item in items {
+                    check_keys(item, s, defs, issues);
+                }
+            }
+        }
+        Node::Scalar(..) => {}
+    }
+}
+
+// follow references and pick the object variant of `Option<T>`
+fn resolve<'a>(schema: &'a Value, defs: &'a Value) -> &'a Value {
+    if let Some(name) = schema["$ref"]
+        .as_str()
+        .and_then(|v| v.strip_prefix("#/definitions/"))
+    {
+        return resolve(&defs[name], defs);
+    }
+    for key in ["allOf", "anyOf"] {
+        if let Some(v) = schema[key].as_array().and_then(|v| {
+            v.iter()
+                .map(|s| resolve(s, defs))
+                .find(|s| s.get("properties").is_some())
+        }) {
+            return v;
+        }
+    }
+    schema
+}
+
+fn check_routes(root: &Node, issues: &mut Vec<ConfigIssue>) {
+    let Some(Node::Map(routes, _)) = root.get("routes") else {
+        return;
+    };
+
+    let mut router = matchit::Router::new();
+    let mut paths = HashSet::new();
+    for (k, v) in routes {
+        let Some(path) = k.as_str() else {
+            continue;
+        };
+        // duplicate paths are already reported by check_keys
+        if !paths.insert(path) {
+            continue;
+        }
+        if let Err(e) = router.insert(path, ()) {
+            issues.push(ConfigIssue::at(k, format!("invalid path `{path}`: {e}")));
+        }
+
+        let Node::Seq(items, _) = v else {
+            continue;
+        };
+        let mut methods = HashSet::new();
+        for item in items {
+            for node in method_nodes(item) {
+                let Some(Ok(method)) = node.as_str().map(|v| v.parse::<RouteMethod>()) else {
+                    continue;
+                };
+                let name = method.to_string();
+                if !methods.insert(name.clone()) {
+                    let msg = format!("duplicate method `{name}` for `{path}`");
+                    issues.push(ConfigIssue::at(node, msg));
+                }
+            }
+
+            let actions = ACTION_KEYS.iter().filter(|k| item.get(k).is_some()).count();
+            if actions != 1 {
+                let msg = format!("a route needs exactly one of {}", ACTION_KEYS.join(", "));
+                issues.push(ConfigIssue::at(item, msg));
+            }
+
+            if let Some(node) = item.get("handler") {
+                let name = node.as_str().unwrap_or_default();
+                if !name.contains("${") && !is_identifier(name) {
+                    let msg = format!("invalid handler name `{name}`, expected a js identifier");
+                    issues.push(ConfigIssue::at(node, msg));
+                }
+            }
+        }
+    }
+}
+
+// report variables that can't be resolved from the environment
+fn check_vars(node: &Node, is_root: bool, issues: &mut Vec<ConfigIssue>) {
+    match node {
+        Node::Map(entries, _) => {
+            for (k, v) in entries {
+                if is_root && k.as_str() == Some("profiles") {
+                    continue;
+                }
+                check_vars(v, false, issues);
+            }
+        }
+        Node::Seq(items, _) => {
+            for item in items {
+                check_vars(item, false, issues);
+            }
+        }
+        Node::Scalar(v, _) => {
+            if let Err(e) = interpolate_str(v, &env_var) {
+                issues.push(ConfigIssue::at(node, e));
+            }
+        }
+    }
+}
+
+fn method_nodes(route: &Node) -> Vec<&Node> {
+    match route.get("method") {
+        Some(Node::Seq(items, _)) => items.iter().collect(),
+        Some(node) => vec![node],
+        None => vec![],
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '$')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"---
+name: test
+limts:
+  body: 1mb
+routes:
+  /api/hello/:id:
+    - method: GET
+      handler: hello
+      timout: 5s
+    - method: [POST, get]
+      handler: 2hello
+  /api/hello/:name:
+    - method: GET
+      handler: hello
+"#;
+
+    #[test]
+    fn validate_config_should_report_every_issue() {
+        let issues = validate_config(CONFIG, None);
+        let positions = issues
+            .iter()
+            .map(|v| (v.line, v.column))
+            .collect::<Vec<_>>();
+        assert_eq!(positions, vec![(3, 1), (9, 7), (10, 22), (11, 16), (12, 3)]);
+        assert!(issues[0].message.starts_with("unknown key `limts`"));
+        assert_eq!(
+            issues[2].message,
+            "duplicate method `GET` for `/api/hello/:id`"
+        );
+        assert!(issues[4].message.contains("conflict"));
+    }
+
+    #[test]
+    fn project_config_parse_should_locate_errors() {
+        let content = "name: test\nroutes: 42\n";
+        let err = ProjectConfig::parse(content, "config.yml", None).unwrap_err();
+        assert_eq!(err.issues.len(), 1);
+        assert!(err
+            .to_string()
+            .starts_with("config.yml:2:9: routes: invalid type"));
+
+        let err = ProjectConfig::parse(CONFIG, "config.yml", None).unwrap_err();
+        assert!(err.to_string().starts_with("config.yml:3:1: unknown key"));
+    }
+
+    #[test]
+    fn project_config_parse_should_locate_profile_errors() {
+        let content = r#"
+name: test
+routes: {}
+profiles:
+  prod:
+    limits:
+      body: ${BODY_SIZE_FOR_TEST}
+    routes:
+      /api:
+        - method: GET
+          handler: hello
+          timeout: soon
+"#;
+        assert!(ProjectConfig::parse(content, "config.yml", None).is_ok());
+        let err = ProjectConfig::parse(content, "config.yml", Some("prod")).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("config.yml:7:13: undefined variable `BODY_SIZE_FOR_TEST`"));
+
+        let content = content.replace("${BODY_SIZE_FOR_TEST}", "${BODY_SIZE_FOR_TEST:-1mb}");
+        let err = ProjectConfig::parse(&content, "config.yml", Some("prod")).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("config.yml:10:11: routes./api[0]: invalid duration `soon`"));
+    }
+
+    #[test]
+    fn project_config_schema_should_describe_routes() {
+        let schema = ProjectConfig::schema();
+        let route = &schema["definitions"]["ProjectRoute"];
+        assert_eq!(route["additionalProperties"], Value::Bool(false));
+        assert!(route["properties"]["timeout"].is_object());
+        assert!(route["properties"]["method"]["anyOf"].is_array());
+    }
+}
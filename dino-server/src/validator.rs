@@ -0,0 +1,229 @@
+use crate::{AppError, ProjectRoute, QueryString};
+use anyhow::{anyhow, Context, Result};
+use jsonschema::JSONSchema;
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::Path,
+};
+
+/// Compiled schemas of a route, requests are checked before js runs.
+#[derive(Debug)]
+pub struct RequestValidator {
+    body: Option<JSONSchema>,
+    query: Option<JSONSchema>,
+    // query keys the schema declares as arrays, sent as arrays even if given once
+    query_arrays: HashSet<String>,
+    params: Option<JSONSchema>,
+}
+
+/// A single reason a request was rejected.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Violation {
+    // body, query or params
+    #[serde(rename = "in")]
+    pub location: &'static str,
+    // json pointer into the checked value, e.g. "/user/name"
+    pub path: String,
+    pub message: String,
+}
+
+impl RequestValidator {
+    /// None if the route declares no schema.
+    pub fn try_new(route: &ProjectRoute) -> Result<Option<Self>> {
+        let query = route.query_schema.as_ref().map(load_schema).transpose();
+        let query = query.context("query_schema")?;
+        let validator = Self {
+            body: compile(route.request_schema.as_ref()).context("request_schema")?,
+            query: compile(query.as_ref()).context("query_schema")?,
+            query_arrays: query.as_ref().map(array_keys).unwrap_or_default(),
+            params: compile(route.params_schema.as_ref()).context("params_schema")?,
+        };
+        let empty =
+            validator.body.is_none() && validator.query.is_none() && validator.params.is_none();
+        Ok((!empty).then_some(validator))
+    }
+
+    /// Params are checked as an object of strings, query keys as strings or,
+    /// when repeated or declared as arrays, arrays of strings, the body as json.
+    pub fn validate(
+        &self,
+        params: &HashMap<String, String>,
+        query: &QueryString,
+        body: &[u8],
+    ) -> Result<(), AppError> {
+        let mut violations = vec![];
+        if let Some(schema) = &self.params {
+            check(schema, &strings(params), "params", &mut violations);
+        }
+        if let Some(schema) = &self.query {
+            check(
+                schema,
+                &query.to_json(&self.query_arrays),
+                "query",
+                &mut violations,
+            );
+        }
+        if let Some(schema) = &self.body {
+            // an empty body is checked as null, so required bodies are reported
+            let body = match body.is_empty() {
+                true => Ok(Value::Null),
+                false => serde_json::from_slice(body),
+            };
+            match body {
+                Ok(v) => check(schema, &v, "body", &mut violations),
+                Err(e) => violations.push(Violation {
+                    location: "body",
+                    path: String::new(),
+                    message: format!("invalid json: {e}"),
+                }),
+            }
+        }
+
+        match violations.is_empty() {
+            true => Ok(()),
+            false => Err(AppError::InvalidRequest(violations)),
+        }
+    }
+}
+
+/// A schema is either inline or the path of a json / yaml file.
+pub fn load_schema(schema: &Value) -> Result<Value> {
+    match schema {
+        Value::String(path) => load_schema_file(Path::new(path)),
+        v => Ok(v.clone()),
+    }
+}
+
+/// Read a json / yaml schema file.
+pub fn load_schema_file(path: &Path) -> Result<Value> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("failed to read schema {}", path.display()))?;
+    let is_json = path.extension().is_some_and(|v| v == "json");
+    let schema = match is_json {
+        true => serde_json::from_str(&content)?,
+        false => serde_yaml::from_str(&content)?,
+    };
+    Ok(schema)
+}
+
+fn compile(schema: Option<&Value>) -> Result<Option<JSONSchema>> {
+    let Some(schema) = schema else {
+        return Ok(None);
+    };
+    let schema = load_schema(schema)?;
+    let compiled = JSONSchema::compile(&schema).map_err(|e| anyhow!("invalid schema: {e}"))?;
+    Ok(Some(compiled))
+}
+
+// `properties` whose `type` is or includes `array`
+fn array_keys(schema: &Value) -> HashSet<String> {
+    let props = schema["properties"].as_object().into_iter().flatten();
+    props
+        .filter(|(_, v)| match &v["type"] {
+            Value::String(t) => t == "array",
+            Value::Array(types) => types.iter().any(|t| t == "array"),
+            _ => false,
+        })
+        .map(|(k, _)| k.clone())
+        .collect()
+}
+
+fn check(schema: &JSONSchema, value: &Value, location: &'static str, ret: &mut Vec<Violation>) {
+    if let Err(errors) = schema.validate(value) {
+        ret.extend(errors.map(|e| Violation {
+            location,
+            path: e.instance_path.to_string(),
+            message: e.to_string(),
+        }));
+    }
+}
+
+fn strings(map: &HashMap<String, String>) -> Value {
+    map.iter()
+        .map(|(k, v)| (k.clone(), Value::String(v.clone())))
+        .collect::<serde_json::Map<_, _>>()
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validator(route: &str) -> RequestValidator {
+        let route: ProjectRoute = serde_yaml::from_str(route).unwrap();
+        RequestValidator::try_new(&route).unwrap().unwrap()
+    }
+
+    #[test]
+    fn validator_should_report_every_violation() {
+        let v = validator(
+            r#"
+method: POST
+handler: createUser
+params_schema:
+  properties:
+    id: { pattern: "^[0-9]+$" }
+query_schema:
+  required: [dry_run]
+  properties:
+    tag: { type: array, items: { enum: [a, b] } }
+request_schema:
+  type: object
+  required: [name]
+  properties:
+    age: { type: integer }
+"#,
+        );
+        let params = HashMap::from([("id".to_string(), "abc".to_string())]);
+        let Err(AppError::InvalidRequest(violations)) =
+            v.validate(&params, &QueryString::default(), br#"{"age": "1"}"#)
+        else {
+            panic!("request should be invalid");
+        };
+        let mut found: Vec<_> = violations
+            .iter()
+            .map(|v| (v.location, v.path.as_str()))
+            .collect();
+        found.sort();
+        let expected = [
+            ("body", ""),
+            ("body", "/age"),
+            ("params", "/id"),
+            ("query", ""),
+        ];
+        assert_eq!(found, expected);
+
+        let params = HashMap::from([("id".to_string(), "1".to_string())]);
+        let query = QueryString::parse(Some("dry_run=1&tag=a&tag=b"));
+        assert!(v.validate(&params, &query, br#"{"name": "a"}"#).is_ok());
+        // a single value of an array key is still an array
+        let query = QueryString::parse(Some("dry_run=1&tag=a"));
+        assert!(v.validate(&params, &query, br#"{"name": "a"}"#).is_ok());
+        let bad = QueryString::parse(Some("dry_run=1&tag=a&tag=c"));
+        assert!(v.validate(&params, &bad, br#"{"name": "a"}"#).is_err());
+        assert!(matches!(
+            v.validate(&params, &query, b"{"),
+            Err(AppError::InvalidRequest(_))
+        ));
+    }
+
+    #[test]
+    fn validator_should_load_schema_files() {
+        let route = "method: POST\nhandler: a\nrequest_schema: fixtures/user.schema.json\n";
+        let v = validator(route);
+        let (params, query) = (HashMap::new(), QueryString::default());
+        assert!(v.validate(&params, &query, br#"{"name": "a"}"#).is_ok());
+        assert!(v.validate(&params, &query, b"{}").is_err());
+
+        let route = "method: GET\nhandler: a\nquery_schema: fixtures/missing.json\n";
+        let route: ProjectRoute = serde_yaml::from_str(route).unwrap();
+        assert!(RequestValidator::try_new(&route).is_err());
+
+        let route = "method: GET\nhandler: a\n";
+        let route: ProjectRoute = serde_yaml::from_str(route).unwrap();
+        assert!(RequestValidator::try_new(&route).unwrap().is_none());
+    }
+}
@@ -0,0 +1,41 @@
+use anyhow::{bail, Result};
+use clap::Args;
+use reqwest::{Method, RequestBuilder, Response};
+
+pub(crate) const ADMIN_TOKEN_ENV: &str = "DINO_ADMIN_TOKEN";
+
+/// Where to reach the admin api of a running server.
+#[derive(Debug, Args)]
+pub struct AdminOpts {
+    // url of the admin api, e.g. "http://localhost:3001"
+    #[arg(long, default_value = "http://localhost:3001")]
+    pub admin_url: String,
+    // bearer token of the admin api, read from DINO_ADMIN_TOKEN if not set
+    #[arg(long)]
+    pub admin_token: Option<String>,
+}
+
+impl AdminOpts {
+    pub(crate) fn request(&self, method: Method, path: &str) -> Result<RequestBuilder> {
+        let Some(token) = admin_token(self.admin_token.clone()) else {
+            bail!("admin api needs --admin-token or {ADMIN_TOKEN_ENV}");
+        };
+        let url = format!("{}{}", self.admin_url.trim_end_matches('/'), path);
+        Ok(reqwest::Client::new()
+            .request(method, url)
+            .bearer_auth(token))
+    }
+}
+
+pub(crate) fn admin_token(token: Option<String>) -> Option<String> {
+    token.or_else(|| std::env::var(ADMIN_TOKEN_ENV).ok())
+}
+
+// errors of the admin api are plain text
+pub(crate) async fn check(res: Response) -> Result<Response> {
+    if !res.status().is_success() {
+        let status = res.status();
+        bail!("{}: {}", status, res.text().await?);
+    }
+    Ok(res)
+}
@@ -4,12 +4,16 @@ use crate::{build_project, CmdExector};
 use clap::Parser;
 
 #[derive(Debug, Parser)]
-pub struct BuildOpts {}
+pub struct BuildOpts {
+    // profile of config.yml to build with, e.g. "prod"
+    #[arg(long)]
+    pub profile: Option<String>,
+}
 
 impl CmdExector for BuildOpts {
     async fn execute(self) -> anyhow::Result<()> {
         let cur_dir = env::current_dir()?.display().to_string();
-        let filename = build_project(&cur_dir)?;
+        let filename = build_project(&cur_dir, self.profile.as_deref())?;
         eprintln!("Build success: {}", filename);
 
         Ok(())
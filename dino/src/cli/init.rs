@@ -3,6 +3,7 @@ use anyhow::Result;
 use askama::Template;
 use clap::Parser;
 use dialoguer::Input;
+use dino_server::ProjectConfig;
 use git2::Repository;
 use std::{fs, path::Path};
 
@@ -47,6 +48,9 @@ fn init_project(name: &str, path: &Path) -> Result<()> {
         name: name.to_string(),
     };
     fs::write(path.join("config.yml"), config.render()?)?;
+    // init config schema for editor completion
+    let schema = serde_json::to_string_pretty(&ProjectConfig::schema())?;
+    fs::write(path.join("config.schema.json"), schema)?;
     // init main.ts file
     fs::write(path.join("main.ts"), MainTsFile {}.render()?)?;
     // init .gitignore file
@@ -1,11 +1,18 @@
 mod build;
 mod init;
+mod openapi;
+mod rollback;
 mod run;
+mod schema;
+mod versions;
 
 use clap::Parser;
 use enum_dispatch::enum_dispatch;
 
-pub use self::{build::BuildOpts, init::InitOpts, run::RunOpts};
+pub use self::{
+    build::BuildOpts, init::InitOpts, openapi::OpenApiOpts, rollback::RollbackOpts, run::RunOpts,
+    schema::SchemaOpts, versions::VersionsOpts,
+};
 
 #[derive(Debug, Parser)]
 #[command(name = "dino", version, author, about, long_about = None)]
@@ -23,4 +30,18 @@ pub enum SubCommand {
     Build(BuildOpts),
     #[command(name = "run", about = "Run user's dino project")]
     Run(RunOpts),
+    #[command(name = "schema", about = "Print JSON Schema of config.yml")]
+    Schema(SchemaOpts),
+    #[command(
+        name = "openapi",
+        about = "Print OpenAPI document of the project routes"
+    )]
+    OpenApi(OpenApiOpts),
+    #[command(name = "versions", about = "List recent versions of a running tenant")]
+    Versions(VersionsOpts),
+    #[command(
+        name = "rollback",
+        about = "Roll a running tenant back to a previous version"
+    )]
+    Rollback(RollbackOpts),
 }
@@ -0,0 +1,31 @@
+use crate::CmdExector;
+use clap::Parser;
+use dino_server::{openapi_document, ProjectConfig};
+use std::{fs, path::PathBuf};
+
+#[derive(Debug, Parser)]
+pub struct OpenApiOpts {
+    /// Write the document to this file instead of stdout
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+    // profile of config.yml to describe, e.g. "prod"
+    #[arg(long)]
+    pub profile: Option<String>,
+}
+
+impl CmdExector for OpenApiOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let mut config = ProjectConfig::load("config.yml", self.profile.as_deref())?;
+        config.inline_schemas(".")?;
+        let document = serde_json::to_string_pretty(&openapi_document(&config)?)?;
+        match self.output {
+            Some(path) => {
+                fs::write(&path, document)?;
+                eprintln!("OpenAPI document written to: {}", path.display());
+            }
+            None => println!("{}", document),
+        }
+
+        Ok(())
+    }
+}
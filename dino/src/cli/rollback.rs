@@ -0,0 +1,30 @@
+use crate::{check, AdminOpts, CmdExector};
+use clap::Parser;
+use dino_server::{Rollback, VersionInfo};
+use reqwest::Method;
+
+#[derive(Debug, Parser)]
+pub struct RollbackOpts {
+    // host of the tenant
+    #[arg(long, default_value = "localhost")]
+    pub host: String,
+    // build hash to go back to, the previous version if not set
+    #[arg(long)]
+    pub to: Option<String>,
+    #[command(flatten)]
+    pub admin: AdminOpts,
+}
+
+impl CmdExector for RollbackOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        // the host may carry a base path, so it goes in the query
+        let path = "/tenants/-/rollback";
+        let body = Rollback { to: self.to };
+        let req = self.admin.request(Method::POST, path)?;
+        let req = req.query(&[("key", &self.host)]).json(&body);
+        let version: VersionInfo = check(req.send().await?).await?.json().await?;
+        eprintln!("Rolled {} back to: {}", self.host, version.hash);
+
+        Ok(())
+    }
+}
@@ -1,6 +1,12 @@
-use crate::{build_project, CmdExector};
+use crate::{
+    admin_token, assets_dir, build_project, load_build_info, schemas_dir, CmdExector,
+    ADMIN_TOKEN_ENV, BUILD_DIR,
+};
 use clap::Parser;
-use dino_server::{start_server, ProjectConfig, SwappableAppRouter, TenentRouter};
+use dino_server::{
+    parse_size, start_server, AdminConfig, AppRouterInner, CertFiles, ListenAddr, ProjectConfig,
+    RequestLimits, ServerConfig, SwappableAppRouter, TenentRouter, TlsConfig, DEFAULT_HOST,
+};
 use notify::RecursiveMode;
 use notify_debouncer_mini::{new_debouncer, DebounceEventResult};
 use std::{fs, path::Path, time::Duration, vec};
@@ -16,6 +22,58 @@ pub struct RunOpts {
     // port to listen
     #[arg(short, long, default_value = "3000")]
     pub port: u16,
+    // repeatable, host:port, [::1]:port, unix:PATH, fd:N or systemd, replaces --port
+    #[arg(long)]
+    pub listen: Vec<ListenAddr>,
+    // max request body size for every route, e.g. "10mb"
+    #[arg(long, value_parser = parse_size)]
+    pub max_body_size: Option<usize>,
+    // max number of request headers
+    #[arg(long)]
+    pub max_headers: Option<usize>,
+    // max length of the request url
+    #[arg(long, value_parser = parse_size)]
+    pub max_url_length: Option<usize>,
+    // profile of config.yml to run with, e.g. "dev"
+    #[arg(long)]
+    pub profile: Option<String>,
+    // hosts to serve, exact or patterns like "*.preview.example.com" / ".example.com",
+    // optionally mounted under a base path, e.g. "example.com/billing"
+    #[arg(long = "host", default_value = "localhost")]
+    pub hosts: Vec<String>,
+    // also serve requests for hosts no other tenant matches
+    #[arg(long)]
+    pub default_tenant: bool,
+    // port of the admin api, disabled if not set
+    #[arg(long)]
+    pub admin_port: Option<u16>,
+    // repeatable, where the admin api listens instead of 127.0.0.1:<admin-port>
+    #[arg(long, requires = "admin_port")]
+    pub admin_listen: Vec<ListenAddr>,
+    // repeatable, serve /metrics without auth there, it's only on the admin api otherwise
+    #[arg(long)]
+    pub metrics_listen: Vec<ListenAddr>,
+    // bearer token of the admin api, read from DINO_ADMIN_TOKEN if not set
+    #[arg(long)]
+    pub admin_token: Option<String>,
+    // port to serve https on, disabled if not set
+    #[arg(long, requires_all = ["tls_cert", "tls_key"])]
+    pub tls_port: Option<u16>,
+    // PEM certificate chain, reloaded when it changes
+    #[arg(long)]
+    pub tls_cert: Option<String>,
+    // PEM private key of the certificate
+    #[arg(long)]
+    pub tls_key: Option<String>,
+    // redirect plain http requests to https
+    #[arg(long, requires = "tls_port")]
+    pub redirect_http: bool,
+    // send the build hash in x-dino-version instead of stable / canary
+    #[arg(long)]
+    pub version_hash: bool,
+    // seconds in-flight requests get to finish on SIGINT / SIGTERM
+    #[arg(long, default_value = "30")]
+    pub drain_timeout: u64,
 }
 
 impl CmdExector for RunOpts {
@@ -23,29 +81,96 @@ impl CmdExector for RunOpts {
         let layer = Layer::new().with_filter(LevelFilter::INFO);
         tracing_subscriber::registry().with(layer).init();
 
-        let (code, config) = get_code_and_config()?;
-
-        let router = SwappableAppRouter::try_new(&code, config.routes)?;
-        let routers = vec![TenentRouter::new("localhost", router.clone())];
-
-        tokio::spawn(async_watch(".", router));
-
-        start_server(self.port, routers).await?;
+        let router = SwappableAppRouter::from(load_build(self.profile.as_deref())?);
+        let mut hosts = self.hosts.iter();
+        let host = hosts.next().map_or("localhost", |v| v.as_str());
+        let mut tenant = TenentRouter::new(host, router.clone());
+        for alias in hosts {
+            tenant = tenant.with_alias(alias);
+        }
+        if self.default_tenant {
+            tenant = tenant.with_alias(DEFAULT_HOST);
+        }
+        let routers = vec![tenant];
+
+        tokio::spawn(async_watch(".", router, self.profile));
+
+        let limits = RequestLimits {
+            body: self.max_body_size,
+            headers: self.max_headers,
+            url: self.max_url_length,
+        };
+        let admin = match self.admin_port {
+            Some(port) => {
+                let token = admin_token(self.admin_token).ok_or_else(|| {
+                    anyhow::anyhow!("--admin-port needs --admin-token or {ADMIN_TOKEN_ENV}")
+                })?;
+                Some(AdminConfig {
+                    port,
+                    listen: self.admin_listen,
+                    token,
+                })
+            }
+            None => None,
+        };
+        let tls = match (self.tls_port, self.tls_cert, self.tls_key) {
+            (Some(port), Some(cert), Some(key)) => Some(
+                TlsConfig::builder()
+                    .port(port)
+                    .default_cert(Some(CertFiles::new(cert, key)))
+                    .redirect_http(self.redirect_http)
+                    .build(),
+            ),
+            _ => None,
+        };
+        let config = ServerConfig::builder()
+            .port(self.port)
+            .listen(self.listen)
+            .metrics_listen(self.metrics_listen)
+            .limits(limits)
+            .admin(admin)
+            .tls(tls)
+            .handle_signals(true)
+            .version_hash(self.version_hash)
+            .drain_timeout(Duration::from_secs(self.drain_timeout))
+            .build();
+        start_server(config, routers).await?;
 
         Ok(())
     }
 }
 
-fn get_code_and_config() -> anyhow::Result<(String, ProjectConfig)> {
-    let filename = build_project(".")?;
+// the router is tagged with the hash of the build
+fn load_build(profile: Option<&str>) -> anyhow::Result<AppRouterInner> {
+    let filename = build_project(".", profile)?;
     let config = filename.replace(".mjs", ".yml");
-    let code = fs::read_to_string(filename)?;
-    let config = ProjectConfig::load(config)?;
-    Ok((code, config))
+    let code = fs::read_to_string(&filename)?;
+    // the build records which profile it was made for
+    let info = load_build_info(&filename)?;
+    let mut config = ProjectConfig::load(config, info.profile.as_deref())?;
+    // serve the assets packaged with the build instead of the source dir
+    if let Some(assets) = config.assets.as_mut() {
+        assets.dir = assets_dir(&filename).into();
+    }
+    config.inline_schemas(schemas_dir(&filename))?;
+    let mut inner = AppRouterInner::try_new(code, config)?;
+    inner.hash = info.hash;
+    Ok(inner)
 }
 
-async fn async_watch(p: impl AsRef<Path>, router: SwappableAppRouter) -> anyhow::Result<()> {
+async fn async_watch(
+    p: impl AsRef<Path>,
+    router: SwappableAppRouter,
+    profile: Option<String>,
+) -> anyhow::Result<()> {
     let (tx, rx) = channel(1);
+    let project = ProjectConfig::load("config.yml", profile.as_deref())?;
+    let schemas: Vec<_> = project
+        .schema_files()
+        .into_iter()
+        .filter_map(|v| fs::canonicalize(v).ok())
+        .collect();
+    let assets = project.assets.and_then(|v| fs::canonicalize(v.dir).ok());
 
     let mut debouncer = new_debouncer(MONITOR_FS_INTERVAL, move |res: DebounceEventResult| {
         tx.blocking_send(res).unwrap();
@@ -60,11 +185,22 @@ async fn async_watch(p: impl AsRef<Path>, router: SwappableAppRouter) -> anyhow:
         match ret {
             Ok(events) => {
                 let mut need_swap = false;
-                // config.yml change, or any ".ts" / ".js" file change
+                // config.yml change, any ".ts" / ".js" file change, or a schema file
                 for event in events {
                     let path = event.path;
+                    // build output (including copied assets) never triggers a rebuild
+                    if path.components().any(|c| c.as_os_str() == BUILD_DIR) {
+                        continue;
+                    }
                     let ext = path.extension().unwrap_or_default();
-                    if path.ends_with("config.yml") || ext == "ts" || ext == "js" {
+                    let is_asset = assets.as_ref().is_some_and(|dir| path.starts_with(dir));
+                    let is_schema = schemas.contains(&path);
+                    if path.ends_with("config.yml")
+                        || ext == "ts"
+                        || ext == "js"
+                        || is_asset
+                        || is_schema
+                    {
                         info!("File changed: {}", path.display());
                         need_swap = true;
                         break;
@@ -72,8 +208,7 @@ async fn async_watch(p: impl AsRef<Path>, router: SwappableAppRouter) -> anyhow:
                 }
 
                 if need_swap {
-                    let (code, config) = get_code_and_config()?;
-                    router.swap(code, config.routes)?;
+                    router.store(load_build(profile.as_deref())?, "watch");
                 }
             }
             Err(e) => {
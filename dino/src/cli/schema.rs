@@ -0,0 +1,26 @@
+use crate::CmdExector;
+use clap::Parser;
+use dino_server::ProjectConfig;
+use std::{fs, path::PathBuf};
+
+#[derive(Debug, Parser)]
+pub struct SchemaOpts {
+    /// Write the schema to this file instead of stdout
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+impl CmdExector for SchemaOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let schema = serde_json::to_string_pretty(&ProjectConfig::schema())?;
+        match self.output {
+            Some(path) => {
+                fs::write(&path, schema)?;
+                eprintln!("Schema written to: {}", path.display());
+            }
+            None => println!("{}", schema),
+        }
+
+        Ok(())
+    }
+}
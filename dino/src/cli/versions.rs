@@ -0,0 +1,29 @@
+use crate::{check, AdminOpts, CmdExector};
+use clap::Parser;
+use dino_server::VersionInfo;
+use reqwest::Method;
+
+#[derive(Debug, Parser)]
+pub struct VersionsOpts {
+    // host of the tenant
+    #[arg(long, default_value = "localhost")]
+    pub host: String,
+    #[command(flatten)]
+    pub admin: AdminOpts,
+}
+
+impl CmdExector for VersionsOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        // the host may carry a base path, so it goes in the query
+        let path = "/tenants/-/versions";
+        let req = self.admin.request(Method::GET, path)?;
+        let res = check(req.query(&[("key", &self.host)]).send().await?).await?;
+        let versions: Vec<VersionInfo> = res.json().await?;
+        for v in versions {
+            let current = if v.current { "*" } else { " " };
+            println!("{} {}  {}  {}", current, v.hash, v.created_at, v.source);
+        }
+
+        Ok(())
+    }
+}
@@ -1,8 +1,11 @@
+mod admin;
 mod cli;
 mod utils;
 
 use enum_dispatch::enum_dispatch;
 
+pub use admin::AdminOpts;
+pub(crate) use admin::*;
 pub use cli::*;
 pub(crate) use utils::*;
 
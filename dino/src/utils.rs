@@ -1,15 +1,25 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
 use bundler::run_bundle;
+use dino_server::ProjectConfig;
 use glob::{glob, GlobError};
+use serde::{Deserialize, Serialize};
 use std::{
     collections::BTreeSet,
     fs::{self, File},
     io,
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
 };
 
 use crate::BUILD_DIR;
 
+/// Metadata written next to every build.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct BuildInfo {
+    pub hash: String,
+    // profile of config.yml the build was made for
+    pub profile: Option<String>,
+}
+
 // get all files with certain extension in a directory
 pub(crate) fn get_files_with_exts(dir: &str, exts: &[&str]) -> Result<BTreeSet<PathBuf>> {
     // glob all ts files
@@ -22,12 +32,37 @@ pub(crate) fn get_files_with_exts(dir: &str, exts: &[&str]) -> Result<BTreeSet<P
     Ok(files)
 }
 
-pub(crate) fn calc_project_hash(dir: &str) -> Result<String> {
-    calc_hash_for_files(dir, &["ts", "js", "json"], 16)
+// get all files in a directory, recursively
+pub(crate) fn get_all_files(dir: &Path) -> Result<BTreeSet<PathBuf>> {
+    let rule = format!("{}/**/*", dir.display());
+    let paths = glob(&rule)?.collect::<Result<BTreeSet<PathBuf>, GlobError>>()?;
+    Ok(paths.into_iter().filter(|p| p.is_file()).collect())
+}
+
+pub(crate) fn calc_project_hash(
+    dir: &str,
+    assets: Option<&Path>,
+    schemas: &[PathBuf],
+    profile: Option<&str>,
+) -> Result<String> {
+    let mut files = get_files_with_exts(dir, &["ts", "js", "json"])?;
+    files.insert(Path::new(dir).join("config.yml"));
+    // assets and schema files are packaged with the build, so they are part of its hash
+    if let Some(assets) = assets {
+        files.extend(get_all_files(assets)?);
+    }
+    files.extend(schemas.iter().map(|v| Path::new(dir).join(v)));
+    // build output is never part of the hash
+    files.retain(|p| p.is_file() && !p.components().any(|c| c.as_os_str() == BUILD_DIR));
+    let hash = calc_hash_for_files(files, 16)?;
+    // the same sources built for different profiles are different builds
+    Ok(match profile {
+        Some(profile) => format!("{}-{}", hash, profile),
+        None => hash,
+    })
 }
 
-pub(crate) fn calc_hash_for_files(dir: &str, exts: &[&str], len: usize) -> Result<String> {
-    let files = get_files_with_exts(dir, exts)?;
+pub(crate) fn calc_hash_for_files(files: BTreeSet<PathBuf>, len: usize) -> Result<String> {
     let mut hasher = blake3::Hasher::new();
     for file in files {
         hasher.update_reader(File::open(file)?)?;
@@ -37,8 +72,21 @@ pub(crate) fn calc_hash_for_files(dir: &str, exts: &[&str], len: usize) -> Resul
     Ok(ret)
 }
 
-pub(crate) fn build_project(dir: &str) -> Result<String> {
-    let hash = calc_project_hash(dir)?;
+pub(crate) fn build_project(dir: &str, profile: Option<&str>) -> Result<String> {
+    let project = ProjectConfig::load("config.yml", profile)?;
+    let assets = project.assets.as_ref().map(|v| v.dir.as_path());
+    let schemas = project.schema_files();
+    // schema files are copied into the build, so they must stay in the project
+    if let Some(file) = schemas
+        .iter()
+        .find(|v| v.components().any(|c| !matches!(c, Component::Normal(_))))
+    {
+        bail!(
+            "schema {} must be a path inside the project",
+            file.display()
+        );
+    }
+    let hash = calc_project_hash(dir, assets, &schemas, profile)?;
     fs::create_dir_all(BUILD_DIR)?;
     let filename = format!("{}/{}.mjs", BUILD_DIR, hash);
     let config = format!("{}/{}.yml", BUILD_DIR, hash);
@@ -54,10 +102,58 @@ pub(crate) fn build_project(dir: &str) -> Result<String> {
     let mut dst = File::create(config)?;
     let mut src = File::open("config.yml")?;
     io::copy(&mut src, &mut dst)?;
+    if let Some(assets) = assets {
+        copy_dir(assets, Path::new(&assets_dir(&filename)))?;
+    }
+    for file in schemas {
+        let target = Path::new(&schemas_dir(&filename)).join(&file);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(Path::new(dir).join(&file), target)?;
+    }
+    let info = BuildInfo {
+        hash,
+        profile: profile.map(|v| v.to_string()),
+    };
+    fs::write(
+        build_info_file(&filename),
+        serde_json::to_string_pretty(&info)?,
+    )?;
 
     Ok(filename)
 }
 
+pub(crate) fn load_build_info(filename: &str) -> Result<BuildInfo> {
+    let content = fs::read_to_string(build_info_file(filename))?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+fn build_info_file(filename: &str) -> String {
+    filename.replace(".mjs", ".json")
+}
+
+// packaged assets of a build live next to its .mjs file
+pub(crate) fn assets_dir(filename: &str) -> String {
+    filename.replace(".mjs", ".assets")
+}
+
+// schema files of a build, paths relative to the project are kept
+pub(crate) fn schemas_dir(filename: &str) -> String {
+    filename.replace(".mjs", ".schemas")
+}
+
+fn copy_dir(src: &Path, dst: &Path) -> Result<()> {
+    for file in get_all_files(src)? {
+        let target = dst.join(file.strip_prefix(src)?);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(&file, target)?;
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -77,10 +173,29 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn get_all_files_should_work() -> Result<()> {
+        let files = get_all_files(Path::new("fixtures/prj/test2"))?;
+        assert_eq!(
+            files.into_iter().collect::<Vec<_>>(),
+            [PathBuf::from("fixtures/prj/test2/test3/d.json")]
+        );
+        Ok(())
+    }
+
     #[test]
     fn calc_hash_for_files_should_work() -> Result<()> {
-        let hash = calc_hash_for_files("fixtures/prj", &["ts", "js", "json"], 12)?;
+        let files = get_files_with_exts("fixtures/prj", &["ts", "js", "json"])?;
+        let hash = calc_hash_for_files(files, 12)?;
         assert_eq!(hash, "af1349b9f5f9");
         Ok(())
     }
+
+    #[test]
+    fn calc_project_hash_should_include_profile() -> Result<()> {
+        let hash = calc_project_hash("fixtures/prj", None, &[], None)?;
+        let prod = calc_project_hash("fixtures/prj", None, &[], Some("prod"))?;
+        assert_eq!(prod, format!("{}-prod", hash));
+        Ok(())
+    }
 }